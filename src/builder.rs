@@ -0,0 +1,90 @@
+use embedded_serial::{NonBlockingRx, NonBlockingTx};
+use super::{Endianness, FramedConnection, LengthWidth, Result};
+
+/// Configure a `FramedConnection` before creating it.
+///
+/// Each option corresponds to a setter of `FramedConnection`, and options
+/// which are not set keep the defaults of `FramedConnection::new()`, so
+/// `FramedConnectionBuilder::new().build(s)` is the same as
+/// `FramedConnection::new(s)`. Both ends of a link must be configured alike.
+#[derive(Debug, Clone, Default)]
+pub struct FramedConnectionBuilder {
+    sentinel: Option<u8>,
+    length_width: Option<LengthWidth>,
+    endianness: Option<Endianness>,
+    checksum: Option<bool>,
+    max_recv_len: Option<usize>,
+    escaping: Option<bool>,
+}
+
+impl FramedConnectionBuilder {
+    /// Constructor
+    pub fn new() -> FramedConnectionBuilder {
+        FramedConnectionBuilder::default()
+    }
+
+    /// See `FramedConnection::set_sentinel()`.
+    pub fn sentinel(mut self, sentinel: u8) -> FramedConnectionBuilder {
+        self.sentinel = Some(sentinel);
+        self
+    }
+
+    /// See `FramedConnection::set_length_width()`.
+    pub fn length_width(mut self, width: LengthWidth) -> FramedConnectionBuilder {
+        self.length_width = Some(width);
+        self
+    }
+
+    /// See `FramedConnection::set_endianness()`.
+    pub fn endian<E: Into<Endianness>>(mut self, endianness: E) -> FramedConnectionBuilder {
+        self.endianness = Some(endianness.into());
+        self
+    }
+
+    /// See `FramedConnection::set_checksum()`.
+    pub fn checksum(mut self, enabled: bool) -> FramedConnectionBuilder {
+        self.checksum = Some(enabled);
+        self
+    }
+
+    /// See `FramedConnection::new_with_max_recv_len()`.
+    pub fn max_recv_len(mut self, max: usize) -> FramedConnectionBuilder {
+        self.max_recv_len = Some(max);
+        self
+    }
+
+    /// See `FramedConnection::set_escaping()`.
+    pub fn escaping(mut self, enabled: bool) -> FramedConnectionBuilder {
+        self.escaping = Some(enabled);
+        self
+    }
+
+    /// Create the `FramedConnection`. Takes ownership of the serial device.
+    ///
+    /// Returns an `Error` if the options conflict, as when escaping is
+    /// enabled with `ESC` as the sentinel.
+    pub fn build<S>(&self, s: S) -> Result<FramedConnection<S>>
+        where S: NonBlockingRx + NonBlockingTx,
+    {
+        let mut conn = match self.max_recv_len {
+            Some(max) => FramedConnection::new_with_max_recv_len(s, max),
+            None => FramedConnection::new(s),
+        };
+        if let Some(sentinel) = self.sentinel {
+            conn.set_sentinel(sentinel)?;
+        }
+        if let Some(width) = self.length_width {
+            conn.set_length_width(width);
+        }
+        if let Some(endianness) = self.endianness {
+            conn.set_endianness(endianness);
+        }
+        if let Some(enabled) = self.checksum {
+            conn.set_checksum(enabled);
+        }
+        if let Some(enabled) = self.escaping {
+            conn.set_escaping(enabled)?;
+        }
+        Ok(conn)
+    }
+}
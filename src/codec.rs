@@ -0,0 +1,1018 @@
+use byteorder::{ByteOrder, LittleEndian};
+use super::{Error, ErrorKind, FrameConfig, FrameInfo, FramingMode, LengthWidth, Endianness, RecvProgress, Result,
+            ESC, MAX_MAGIC_LEN, MAX_VARINT_LEN, VARINT_MORE};
+
+#[cfg(feature = "collections")]
+use collections::vec::Vec;
+
+/// Header flag marking a chunk which is followed by more of the same frame.
+const CHUNK_MORE: u8 = 0x01;
+
+/// The initial value of the CRC-16 (CCITT) checksum.
+const CRC16_INIT: u16 = 0xFFFF;
+
+/// Update a CRC-16 (CCITT) checksum with `data`.
+fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// The most receive buffer allocated up front for an unchecked frame length.
+///
+/// A corrupt header may declare any length, so larger frames grow the
+/// buffer as their bytes arrive.
+const MAX_RECV_RESERVE: usize = 4096;
+
+/// The longest header: the magic, a tag, a 5 byte varint length and a flags byte.
+const MAX_HEADER_LEN: usize = MAX_MAGIC_LEN + MAX_VARINT_LEN + 2;
+
+enum WhatNext {
+    Sentinel,
+    Header,
+    Data,
+    Checksum,
+    Done,
+}
+
+/// The wire bytes of one frame, produced one at a time.
+///
+/// `peek()` returns the next byte and `advance()` moves past it once the
+/// serial device accepted it, so a byte which was not accepted is offered
+/// again. The sentinel and the bytes to escape are looked up in the
+/// `FrameConfig` passed to each call; all other options are those passed
+/// to `new()`.
+pub struct FrameEncoder {
+    what_next: WhatNext,
+    index: usize,
+    header_bytes: [u8; MAX_HEADER_LEN],
+    /// The number of magic bytes at the start of `header_bytes`.
+    magic_len: usize,
+    /// Sent as the first header byte after the magic if tagging is enabled.
+    tag: Option<u8>,
+    length_width: LengthWidth,
+    endianness: Endianness,
+    /// The number of header bytes to send, 0 if there is no header.
+    header_len: usize,
+    frame: Vec<u8>,
+    /// The range of `frame` sent in the current chunk.
+    chunk_start: usize,
+    chunk_end: usize,
+    max_chunk: Option<usize>,
+    /// Whether each chunk is followed by a checksum.
+    with_checksum: bool,
+    checksum_bytes: [u8; 2],
+    /// Sent instead of the computed checksum of each chunk.
+    crc_override: Option<u16>,
+    /// Whether sentinel and `ESC` bytes after the sentinel are escaped.
+    escape: bool,
+    /// Whether `ESC` was sent and the escaped byte is due.
+    escaped: bool,
+}
+
+impl FrameEncoder {
+    /// Prepare to send `frame` as `config` describes, with `tag` if it has a header.
+    ///
+    /// Returns an `Error` if `config` cannot send a frame of this length or
+    /// content.
+    pub fn new(config: &FrameConfig, tag: Option<u8>, frame: Vec<u8>) -> Result<FrameEncoder> {
+        FrameEncoder::with_crc(config, tag, frame, None)
+    }
+
+    /// Like `new()`, but each chunk is followed by `crc` if it is given,
+    /// rather than by its checksum.
+    pub fn with_crc(config: &FrameConfig, tag: Option<u8>, frame: Vec<u8>, crc: Option<u16>)
+        -> Result<FrameEncoder>
+    {
+        let length_prefixed = config.framing_mode == FramingMode::LengthPrefixed;
+        let (header_len, max_chunk) = match config.framing_mode {
+            FramingMode::LengthPrefixed => {
+                // a placeholder, set for each chunk by `start_chunk()`
+                match config.auto_chunk {
+                    Some(max_chunk) => {
+                        let max_chunk = core::cmp::min(max_chunk as u64, config.length_width.max()) as usize;
+                        (config.length_width.len() + 1, Some(max_chunk))
+                    },
+                    None => {
+                        if frame.len() as u64 > config.length_width.max() {
+                            return Err(Error::from_kind(ErrorKind::FrameTooLong));
+                        }
+                        (config.length_width.len(), None)
+                    },
+                }
+            },
+            FramingMode::FixedSize(size) => {
+                if frame.len() != size {
+                    return Err(Error::from_kind(ErrorKind::WrongFrameSize));
+                }
+                (0, None)
+            },
+        };
+        let magic_len = if length_prefixed { config.magic_len } else { 0 };
+        let mut header_bytes = [0; MAX_HEADER_LEN];
+        match (header_bytes.get_mut(..magic_len), config.magic.get(..magic_len)) {
+            (Some(dst), Some(magic)) => dst.copy_from_slice(magic),
+            _ => return Err(Error::with_detail(ErrorKind::Internal, "magic out of range".into())),
+        }
+        let mut encoder = FrameEncoder {
+            what_next: WhatNext::Sentinel,
+            index: 0,
+            header_bytes,
+            magic_len,
+            tag: if length_prefixed { tag } else { None },
+            length_width: config.length_width,
+            endianness: config.endianness,
+            header_len,
+            frame,
+            chunk_start: 0,
+            chunk_end: 0,
+            max_chunk,
+            with_checksum: config.checksum,
+            checksum_bytes: [0; 2],
+            crc_override: crc,
+            escape: config.escaping,
+            escaped: false,
+        };
+        encoder.start_chunk(0)?;
+        if config.sentinel_is_idle {
+            let first = if encoder.header_len > 0 {
+                encoder.header_bytes.first()
+            } else if !encoder.frame.is_empty() || !encoder.with_checksum {
+                encoder.frame.first()
+            } else {
+                encoder.checksum_bytes.first()
+            };
+            if first == Some(&config.sentinel) {
+                return Err(Error::with_detail(ErrorKind::InvalidFrame,
+                    "frame would start with the sentinel, which is idle".into()));
+            }
+        }
+        Ok(encoder)
+    }
+
+    /// Prepare to send the chunk of `frame` beginning at `start`.
+    fn start_chunk(&mut self, start: usize) -> Result<()> {
+        let remaining = self.frame.len().saturating_sub(start);
+        let chunk_len = match self.max_chunk {
+            Some(max_chunk) => core::cmp::min(remaining, max_chunk),
+            None => remaining,
+        };
+        self.chunk_start = start;
+        self.chunk_end = start.saturating_add(chunk_len);
+        let tag_len = match self.tag {
+            Some(tag) => {
+                match self.header_bytes.get_mut(self.magic_len) {
+                    Some(b) => *b = tag,
+                    None => return Err(Error::with_detail(ErrorKind::Internal, "header index out of range".into())),
+                }
+                1
+            },
+            None => 0,
+        };
+        let start = self.magic_len.saturating_add(tag_len);
+        let field = match self.header_bytes.get_mut(start..) {
+            Some(field) => field,
+            None => return Err(Error::with_detail(ErrorKind::Internal, "header index out of range".into())),
+        };
+        let width = self.length_width.write(field, chunk_len, self.endianness)?;
+        let flags = if self.chunk_end < self.frame.len() { CHUNK_MORE } else { 0 };
+        match start.checked_add(width).and_then(|i| self.header_bytes.get_mut(i)) {
+            Some(b) => *b = flags,
+            None => return Err(Error::with_detail(ErrorKind::Internal, "header index out of range".into())),
+        }
+        if self.header_len > 0 {
+            // a varint length changes the header size from chunk to chunk
+            let flags_len = if self.max_chunk.is_some() { 1 } else { 0 };
+            self.header_len = start.saturating_add(width).saturating_add(flags_len);
+        }
+        if self.with_checksum {
+            let header = self.header_bytes.get(..self.header_len).unwrap_or(&[]);
+            let data = self.frame.get(self.chunk_start..self.chunk_end).unwrap_or(&[]);
+            let crc = self.crc_override.unwrap_or_else(|| crc16(crc16(CRC16_INIT, header), data));
+            LittleEndian::write_u16(&mut self.checksum_bytes, crc);
+        }
+        self.what_next = WhatNext::Sentinel;
+        self.index = 0;
+        self.escaped = false;
+        Ok(())
+    }
+
+    /// Move on to the next part of the chunk, skipping empty parts.
+    fn next_part(&mut self) -> Result<()> {
+        self.index = 0;
+        let chunk_empty = self.chunk_start == self.chunk_end;
+        self.what_next = match self.what_next {
+            WhatNext::Sentinel if self.header_len > 0 => WhatNext::Header,
+            WhatNext::Sentinel | WhatNext::Header if !chunk_empty => WhatNext::Data,
+            WhatNext::Sentinel | WhatNext::Header | WhatNext::Data if self.with_checksum => WhatNext::Checksum,
+            _ if self.chunk_end < self.frame.len() => {
+                let start = self.chunk_end;
+                return self.start_chunk(start);
+            },
+            _ => WhatNext::Done,
+        };
+        Ok(())
+    }
+
+    /// The header, data or checksum byte due next, before escaping.
+    fn current(&self) -> Result<u8> {
+        let byte = match self.what_next {
+            WhatNext::Header => self.header_bytes.get(self.index),
+            WhatNext::Data => self.frame.get(self.chunk_start.saturating_add(self.index)),
+            WhatNext::Checksum => self.checksum_bytes.get(self.index),
+            WhatNext::Sentinel | WhatNext::Done => None,
+        };
+        match byte {
+            Some(&byte) => Ok(byte),
+            None => Err(Error::with_detail(ErrorKind::Internal, "send index out of range".into())),
+        }
+    }
+
+    /// The next byte to send, or `None` once the frame was sent.
+    pub fn peek(&self, config: &FrameConfig) -> Result<Option<u8>> {
+        match self.what_next {
+            WhatNext::Sentinel => return Ok(Some(config.sentinel)),
+            WhatNext::Done => return Ok(None),
+            WhatNext::Header | WhatNext::Data | WhatNext::Checksum => {},
+        }
+        let byte = self.current()?;
+        if self.escaped {
+            Ok(Some(byte ^ ESC))
+        } else if self.escape && config.is_special(byte) {
+            Ok(Some(ESC))
+        } else {
+            Ok(Some(byte))
+        }
+    }
+
+    /// Move past the byte returned by `peek()`, after it was sent.
+    pub fn advance(&mut self, config: &FrameConfig) -> Result<()> {
+        let part_len = match self.what_next {
+            WhatNext::Sentinel => 1,
+            WhatNext::Header => self.header_len,
+            WhatNext::Data => self.chunk_end.saturating_sub(self.chunk_start),
+            WhatNext::Checksum => self.checksum_bytes.len(),
+            WhatNext::Done => return Err(Error::with_detail(ErrorKind::Internal, "frame already sent".into())),
+        };
+        if !self.at_chunk_start() && !self.escaped && self.escape && config.is_special(self.current()?) {
+            // after an ESC, the escaped byte is still to come
+            self.escaped = true;
+            return Ok(());
+        }
+        self.escaped = false;
+        self.index = self.index.saturating_add(1);
+        if self.index >= part_len {
+            self.next_part()?;
+        }
+        Ok(())
+    }
+
+    /// The payload bytes due next which are sent as they are.
+    ///
+    /// This is empty unless payload is due, and ends before the next byte
+    /// to escape and at the end of the chunk.
+    pub fn data(&self, config: &FrameConfig) -> Result<&[u8]> {
+        if self.escaped || !matches!(self.what_next, WhatNext::Data) {
+            return Ok(&[]);
+        }
+        let start = self.chunk_start.saturating_add(self.index);
+        let data = match self.frame.get(start..self.chunk_end) {
+            Some(data) => data,
+            None => return Err(Error::with_detail(ErrorKind::Internal, "send index out of range".into())),
+        };
+        if self.escape {
+            // stop before the next byte to escape
+            if let Some(pos) = data.iter().position(|&b| config.is_special(b)) {
+                return Ok(&data[..pos]);
+            }
+        }
+        Ok(data)
+    }
+
+    /// Move past the first `n` bytes returned by `data()`, after they were sent.
+    pub fn advance_data(&mut self, n: usize) -> Result<()> {
+        if !matches!(self.what_next, WhatNext::Data) {
+            return Err(Error::with_detail(ErrorKind::Internal, "payload is not due".into()));
+        }
+        self.index = self.index.saturating_add(n);
+        if self.chunk_start.saturating_add(self.index) >= self.chunk_end {
+            self.next_part()?;
+        }
+        Ok(())
+    }
+
+    /// Send the current chunk again, starting with its sentinel.
+    #[cfg(feature = "collision-detection")]
+    pub fn restart_chunk(&mut self) -> Result<()> {
+        let start = self.chunk_start;
+        self.start_chunk(start)
+    }
+
+    /// Whether the sentinel of a chunk is due.
+    pub fn at_chunk_start(&self) -> bool {
+        matches!(self.what_next, WhatNext::Sentinel)
+    }
+
+    /// Whether the current chunk is the first of the frame.
+    pub fn is_first_chunk(&self) -> bool {
+        self.chunk_start == 0
+    }
+
+    /// Whether the whole frame was sent.
+    pub fn is_done(&self) -> bool {
+        matches!(self.what_next, WhatNext::Done)
+    }
+
+    /// The number of payload bytes sent.
+    pub fn payload_sent(&self) -> usize {
+        match self.what_next {
+            WhatNext::Data => self.chunk_start.saturating_add(self.index),
+            WhatNext::Sentinel | WhatNext::Header => self.chunk_start,
+            WhatNext::Checksum | WhatNext::Done => self.chunk_end,
+        }
+    }
+
+    /// The frame payload.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// The capacity of the frame buffer, in bytes.
+    pub fn capacity(&self) -> usize {
+        self.frame.capacity()
+    }
+
+    /// Return the frame payload.
+    pub fn into_frame(self) -> Vec<u8> {
+        self.frame
+    }
+}
+
+struct HeaderState {
+    sentinel: u8,
+    /// The tag received before the header, if tagging is enabled.
+    tag: Option<u8>,
+    bytes: [u8; MAX_HEADER_LEN],
+    length_width: LengthWidth,
+    endianness: Endianness,
+    /// The number of bytes of the length field, 0 while a varint is incomplete.
+    length_len: usize,
+    /// Whether a flags byte follows the length.
+    chunked: bool,
+    /// The number of header bytes expected.
+    len: usize,
+    index: usize,
+}
+
+impl HeaderState {
+    /// The state for receiving a header after `sentinel` and `tag`.
+    fn new(config: &FrameConfig, sentinel: u8, tag: Option<u8>) -> HeaderState {
+        let length_width = config.length_width;
+        let chunked = config.auto_chunk.is_some();
+        let length_len = match length_width {
+            LengthWidth::Varint => 0,
+            _ => length_width.len(),
+        };
+        HeaderState {
+            sentinel,
+            tag,
+            bytes: [0; MAX_HEADER_LEN],
+            length_width,
+            endianness: config.endianness,
+            length_len,
+            chunked,
+            len: length_width.len() + if chunked { 1 } else { 0 },
+            index: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct DataState {
+    sentinel: u8,
+    tag: Option<u8>,
+    length: usize,
+    /// Whether more chunks of this frame follow.
+    more: bool,
+    /// Whether the data follows the sentinel directly.
+    headerless: bool,
+    /// The checksum of the header and data received so far.
+    crc: u16,
+    /// The checksum received after the data.
+    received_crc: Option<u16>,
+}
+
+struct ChecksumState {
+    data: DataState,
+    bytes: [u8; 2],
+    index: usize,
+}
+
+enum RecvState {
+    Unknown,
+    /// Matching the magic after the sentinel, with the number of bytes matched.
+    Magic(u8, usize),
+    /// Waiting for the tag after the sentinel.
+    Tag(u8),
+    Header(HeaderState),
+    Data(DataState),
+    Checksum(ChecksumState),
+}
+
+impl RecvState {
+    /// The state after the sentinel and magic of a length-prefixed frame.
+    fn after_magic(config: &FrameConfig, sentinel: u8) -> RecvState {
+        if config.tagging {
+            RecvState::Tag(sentinel)
+        } else {
+            RecvState::Header(HeaderState::new(config, sentinel, None))
+        }
+    }
+}
+
+/// The receive half of the framing, fed one byte at a time.
+///
+/// This holds the frame being received, and the completed frame until it
+/// is taken, along with the receive counters. The options are those of the
+/// `FrameConfig` passed to `push()`.
+pub struct Receiver {
+    state: RecvState,
+    buf: Vec<u8>,
+    reassembly_buf: Vec<u8>,
+    escaped: bool,
+    checksum_failed: bool,
+    /// Whether a frame was received with no garbage seen since.
+    synced: bool,
+    noise_limit: Option<usize>,
+    /// Bytes discarded since the last sentinel.
+    noise_run: usize,
+    /// The allowed lengths of frames with each tag, as `(tag, min, max)`.
+    tag_bounds: Vec<(u8, usize, usize)>,
+    /// Frames declaring a greater length are dropped after their header.
+    pub max_recv_len: Option<usize>,
+    /// Consumed silently while waiting for a sentinel.
+    pub idle_byte: Option<u8>,
+    /// Frames growing beyond this are counted in `large_frames`.
+    pub soft_limit: Option<usize>,
+    pub discarded_bytes: u64,
+    pub resyncs: u64,
+    pub checksum_errors: u64,
+    pub large_frames: u64,
+    pub tag_length_errors: u64,
+}
+
+impl Receiver {
+    /// Constructor
+    pub fn new() -> Receiver {
+        Receiver::with_capacity(0)
+    }
+
+    /// A `Receiver` whose buffer holds `cap` bytes.
+    pub fn with_capacity(cap: usize) -> Receiver {
+        Receiver {
+            state: RecvState::Unknown,
+            buf: Vec::with_capacity(cap),
+            reassembly_buf: Vec::new(),
+            escaped: false,
+            checksum_failed: false,
+            synced: false,
+            noise_limit: None,
+            noise_run: 0,
+            tag_bounds: Vec::new(),
+            max_recv_len: None,
+            idle_byte: None,
+            soft_limit: None,
+            discarded_bytes: 0,
+            resyncs: 0,
+            checksum_errors: 0,
+            large_frames: 0,
+            tag_length_errors: 0,
+        }
+    }
+
+    /// Return an `Error` from `push()` after more than `limit` bytes of noise in a row.
+    pub fn set_noise_limit(&mut self, limit: Option<usize>) {
+        self.noise_limit = limit;
+        self.noise_run = 0;
+    }
+
+    /// Drop frames with `tag` unless their length is within `min..=max`, or
+    /// remove the bounds of `tag` with `None`.
+    pub fn set_tag_bounds(&mut self, tag: u8, bounds: Option<(usize, usize)>) {
+        self.tag_bounds.retain(|&(t, _, _)| t != tag);
+        if let Some((min, max)) = bounds {
+            self.tag_bounds.push((tag, min, max));
+        }
+    }
+
+    /// Count a byte discarded while waiting for a sentinel.
+    ///
+    /// Returns an `Error` if this exceeds the noise limit.
+    fn discard_byte(&mut self) -> Result<()> {
+        self.discarded_bytes = self.discarded_bytes.saturating_add(1);
+        if self.synced {
+            self.resyncs = self.resyncs.saturating_add(1);
+            self.synced = false;
+        }
+        self.noise_run = self.noise_run.saturating_add(1);
+        if self.noise_limit.is_some_and(|limit| self.noise_run > limit) {
+            self.noise_run = 0;
+            return Err(Error::from_kind(ErrorKind::RxNoise));
+        }
+        Ok(())
+    }
+
+    /// Whether `byte` is a repeated sentinel ignored by `set_sentinel_is_idle()`.
+    fn is_idle_sentinel(&self, config: &FrameConfig, byte: u8) -> bool {
+        if !config.sentinel_is_idle {
+            return false;
+        }
+        match self.state {
+            RecvState::Magic(sentinel, index) => index == 0 && byte == sentinel,
+            RecvState::Tag(sentinel) => byte == sentinel,
+            RecvState::Header(ref hs) => hs.tag.is_none() && hs.index == 0 && byte == hs.sentinel,
+            RecvState::Data(ref ds) => ds.headerless && self.buf.is_empty() && byte == ds.sentinel,
+            RecvState::Unknown | RecvState::Checksum(_) => false,
+        }
+    }
+
+    /// Decode one byte. Returns whether it completed a frame.
+    ///
+    /// A completed frame must be taken or discarded before the next byte is
+    /// pushed. Returns an `Error` if the noise limit is exceeded.
+    pub fn push(&mut self, config: &FrameConfig, byte: u8) -> Result<bool> {
+        let mut in_frame = !matches!(self.state, RecvState::Unknown);
+        if config.escaping && in_frame && config.is_sentinel(byte) && !self.is_idle_sentinel(config, byte) {
+            // a raw sentinel only starts a frame, so the current one was truncated
+            trace!("framed-serial: sentinel inside frame, resyncing");
+            self.resync();
+            in_frame = false;
+        }
+        let mut was_escaped = false;
+        let byte = if config.escaping && in_frame {
+            if self.escaped {
+                self.escaped = false;
+                was_escaped = true;
+                byte ^ ESC
+            } else if byte == ESC {
+                // the escaped byte follows
+                self.escaped = true;
+                return Ok(false);
+            } else {
+                byte
+            }
+        } else {
+            byte
+        };
+        let mut new_state: Option<RecvState> = None;
+        let mut data_complete = false;
+        let mut checked = false;
+        let mut discard = false;
+        match self.state {
+            RecvState::Unknown => {
+                if config.is_sentinel(byte) {
+                    trace!("framed-serial: frame start, sentinel {=u8:#x}", byte);
+                    self.noise_run = 0;
+                    match config.framing_mode {
+                        FramingMode::LengthPrefixed if config.magic_len > 0 => {
+                            new_state = Some(RecvState::Magic(byte, 0));
+                        },
+                        FramingMode::LengthPrefixed => {
+                            new_state = Some(RecvState::after_magic(config, byte));
+                        },
+                        FramingMode::FixedSize(size) => {
+                            data_complete = size == 0;
+                            new_state = Some(RecvState::Data(DataState{sentinel: byte, tag: None, length: size, more: false, headerless: true, crc: CRC16_INIT, received_crc: None}));
+                        },
+                    }
+                } else if Some(byte) != self.idle_byte {
+                    discard = true;
+                }
+            },
+            RecvState::Magic(sentinel, index) => {
+                if index < config.magic_len && config.magic.get(index) == Some(&byte) {
+                    new_state = Some(if index + 1 == config.magic_len {
+                        RecvState::after_magic(config, sentinel)
+                    } else {
+                        RecvState::Magic(sentinel, index + 1)
+                    });
+                } else if !was_escaped && config.is_sentinel(byte) {
+                    // the sentinel was data, this may be the real frame start
+                    new_state = Some(RecvState::Magic(byte, 0));
+                } else {
+                    new_state = Some(RecvState::Unknown);
+                    discard = true;
+                }
+            },
+            RecvState::Tag(sentinel) if config.sentinel_is_idle && !was_escaped && byte == sentinel => {
+                // still idle
+            },
+            RecvState::Tag(sentinel) => {
+                new_state = Some(RecvState::Header(HeaderState::new(config, sentinel, Some(byte))));
+            },
+            RecvState::Header(ref hs) if config.sentinel_is_idle && !was_escaped &&
+                    hs.tag.is_none() && hs.index == 0 && byte == hs.sentinel => {
+                // still idle
+            },
+            RecvState::Data(ref ds) if config.sentinel_is_idle && ds.headerless && !was_escaped &&
+                    self.buf.is_empty() && byte == ds.sentinel => {
+                // still idle
+            },
+            RecvState::Header(ref mut hs) => {
+                match hs.bytes.get_mut(hs.index) {
+                    Some(b) => *b = byte,
+                    None => {
+                        return Err(Error::with_detail(ErrorKind::Internal, "header index out of range".into()));
+                    },
+                }
+                hs.index = hs.index.saturating_add(1);
+                if hs.length_len == 0 {
+                    let last = byte & VARINT_MORE == 0;
+                    if hs.index == MAX_VARINT_LEN && (!last || byte > 0x0F) {
+                        // too long for a u32, drop the frame
+                        self.resyncs = self.resyncs.saturating_add(1);
+                        self.synced = false;
+                        new_state = Some(RecvState::Unknown);
+                    } else if last {
+                        hs.length_len = hs.index;
+                        hs.len = match hs.index.checked_add(if hs.chunked { 1 } else { 0 }) {
+                            Some(len) => len,
+                            None => {
+                                return Err(Error::with_detail(ErrorKind::Internal, "header length overflow".into()));
+                            },
+                        };
+                    }
+                }
+                if hs.length_len > 0 && hs.index == hs.len {
+                    let flags = if hs.chunked {
+                        match hs.bytes.get(hs.length_len) {
+                            Some(&flags) => flags,
+                            None => {
+                                return Err(Error::with_detail(ErrorKind::Internal, "header index out of range".into()));
+                            },
+                        }
+                    } else {
+                        0
+                    };
+                    let magic_crc = crc16(CRC16_INIT, config.magic());
+                    let tag_crc = match hs.tag {
+                        Some(tag) => crc16(magic_crc, &[tag]),
+                        None => magic_crc,
+                    };
+                    let length = match hs.length_width.read(&hs.bytes, hs.endianness) {
+                        Ok(length) => Some(length),
+                        // too long for a usize, dropped like an overlong varint
+                        Err(ref e) if e.kind() == ErrorKind::FrameTooLong => None,
+                        Err(e) => return Err(e),
+                    };
+                    let reassembled = self.reassembly_buf.len();
+                    let total = length.and_then(|length| reassembled.checked_add(length));
+                    let more = hs.chunked && flags & CHUNK_MORE != 0;
+                    let plausible = match (hs.tag, total) {
+                        (Some(tag), Some(total)) => tag_length_ok(&self.tag_bounds, tag, total, more),
+                        _ => true,
+                    };
+                    if !plausible {
+                        trace!("framed-serial: unexpected length for tag");
+                        self.tag_length_errors = self.tag_length_errors.saturating_add(1);
+                    }
+                    match (length, total) {
+                        (Some(length), Some(total)) if plausible && self.max_recv_len.is_none_or(|max| total <= max) => {
+                            let ds = DataState {
+                                sentinel: hs.sentinel,
+                                tag: hs.tag,
+                                length,
+                                more,
+                                headerless: false,
+                                crc: crc16(tag_crc, hs.bytes.get(..hs.len).unwrap_or(&[])),
+                                received_crc: None,
+                            };
+                            // the length is only checked against `max_recv_len`
+                            let reserve = match self.max_recv_len {
+                                Some(_) => ds.length,
+                                None => core::cmp::min(ds.length, MAX_RECV_RESERVE),
+                            };
+                            self.buf.reserve(reserve);
+                            trace!("framed-serial: header done, length {=usize}", ds.length);
+                            data_complete = ds.length == 0;
+                            new_state = Some(RecvState::Data(ds));
+                        },
+                        _ => {
+                            // most likely a corrupt header, drop the frame
+                            trace!("framed-serial: dropping frame of {=usize} bytes", total.unwrap_or(usize::MAX));
+                            self.resyncs = self.resyncs.saturating_add(1);
+                            self.synced = false;
+                            self.reassembly_buf.clear();
+                            new_state = Some(RecvState::Unknown);
+                        },
+                    }
+                }
+            },
+            RecvState::Data(ref mut ds) => {
+                self.buf.push(byte);
+                if config.checksum {
+                    ds.crc = crc16(ds.crc, &[byte]);
+                }
+                // chunks count towards the reassembled frame
+                let crossed = self.soft_limit.and_then(|l| l.checked_add(1));
+                let received = self.reassembly_buf.len().checked_add(self.buf.len());
+                if crossed.is_some() && crossed == received {
+                    self.large_frames = self.large_frames.saturating_add(1);
+                }
+                data_complete = self.buf.len() == ds.length;
+            },
+            RecvState::Checksum(ref mut cs) => {
+                match cs.bytes.get_mut(cs.index) {
+                    Some(b) => *b = byte,
+                    None => {
+                        return Err(Error::with_detail(ErrorKind::Internal, "checksum index out of range".into()));
+                    },
+                }
+                cs.index = cs.index.saturating_add(1);
+                if cs.index == cs.bytes.len() {
+                    let received_crc = LittleEndian::read_u16(&cs.bytes);
+                    if received_crc != cs.data.crc {
+                        trace!("framed-serial: checksum mismatch");
+                        self.checksum_failed = true;
+                        self.checksum_errors = self.checksum_errors.saturating_add(1);
+                    }
+                    new_state = Some(RecvState::Data(DataState { received_crc: Some(received_crc), ..cs.data }));
+                    data_complete = true;
+                    checked = true;
+                }
+            },
+        };
+        if let Some(ns) = new_state {
+            self.state = ns;
+        }
+        if discard {
+            self.discard_byte()?;
+        }
+        if data_complete && config.checksum && !checked {
+            // the checksum follows the data
+            if let RecvState::Data(ds) = self.state {
+                self.state = RecvState::Checksum(ChecksumState{data: ds, bytes: [0; 2], index: 0});
+            }
+            data_complete = false;
+        }
+        Ok(data_complete && self.data_complete())
+    }
+
+    /// Called when all data of the current chunk was received.
+    ///
+    /// Returns whether a whole frame is now available in `buf`.
+    fn data_complete(&mut self) -> bool {
+        let more = match self.state {
+            RecvState::Data(ref ds) => ds.more,
+            _ => false,
+        };
+        if more {
+            // keep the chunk and wait for the next one
+            self.reassembly_buf.append(&mut self.buf);
+            self.state = RecvState::Unknown;
+            return false;
+        }
+        if !self.reassembly_buf.is_empty() {
+            self.reassembly_buf.append(&mut self.buf);
+            core::mem::swap(&mut self.buf, &mut self.reassembly_buf);
+            if let RecvState::Data(ref mut ds) = self.state {
+                ds.length = self.buf.len();
+            }
+        }
+        if !self.checksum_failed {
+            self.synced = true;
+        }
+        true
+    }
+
+    /// Whether a frame is complete, including one with a wrong checksum.
+    pub fn is_frame_complete(&self) -> bool {
+        match self.state {
+            RecvState::Unknown | RecvState::Magic(..) | RecvState::Tag(_) | RecvState::Header(_) |
+                RecvState::Checksum(_) => false,
+            RecvState::Data(ref ds) => ds.length == self.buf.len(),
+        }
+    }
+
+    /// Whether a frame has started but is not yet complete.
+    pub fn is_receiving(&self) -> bool {
+        match self.state {
+            RecvState::Unknown => !self.reassembly_buf.is_empty(),
+            RecvState::Magic(..) | RecvState::Tag(_) | RecvState::Header(_) | RecvState::Checksum(_) => true,
+            RecvState::Data(_) => !self.is_frame_complete(),
+        }
+    }
+
+    /// The phase of receiving.
+    pub fn progress(&self) -> RecvProgress {
+        match self.state {
+            RecvState::Unknown => RecvProgress::Idle,
+            RecvState::Magic(..) | RecvState::Tag(_) | RecvState::Header(_) => RecvProgress::Header,
+            RecvState::Data(ref ds) => RecvProgress::Data { received: self.buf.len(), length: ds.length },
+            RecvState::Checksum(_) => RecvProgress::Checksum,
+        }
+    }
+
+    /// Whether the completed frame has a wrong checksum.
+    pub fn checksum_failed(&self) -> bool {
+        self.checksum_failed
+    }
+
+    /// Borrow the completed frame, unless it has a wrong checksum.
+    pub fn frame(&self) -> Option<&[u8]> {
+        if self.is_frame_complete() && !self.checksum_failed {
+            Some(&self.buf)
+        } else {
+            None
+        }
+    }
+
+    /// Take the completed frame.
+    ///
+    /// With `retain`, the frame is copied and the buffer is kept. The
+    /// returned `FrameInfo` has index 0 and is not marked as the end of
+    /// transmission. Returns an `Error` if no frame is complete, or of kind
+    /// `ErrorKind::ChecksumMismatch` after discarding a frame with a wrong
+    /// checksum.
+    pub fn take_frame(&mut self, retain: bool) -> Result<FrameInfo> {
+        if self.checksum_failed && self.is_frame_complete() {
+            self.discard_frame();
+            return Err(Error::from_kind(ErrorKind::ChecksumMismatch));
+        }
+        let info = match self.state {
+            RecvState::Data(ref ds) if self.buf.len() == ds.length => {
+                let frame = if retain {
+                    let frame = self.buf.as_slice().to_vec();
+                    self.buf.clear();
+                    frame
+                } else {
+                    let mut frame = Vec::with_capacity(0);
+                    core::mem::swap(&mut self.buf, &mut frame);
+                    frame
+                };
+                FrameInfo {
+                    data: frame,
+                    sentinel: ds.sentinel,
+                    is_eot: false,
+                    index: 0,
+                    tag: ds.tag,
+                    crc: ds.received_crc,
+                }
+            },
+            _ => return Err(Error::from_kind(ErrorKind::FrameNotAvailable)),
+        };
+        self.state = RecvState::Unknown;
+        Ok(info)
+    }
+
+    /// Take the completed frame with a wrong checksum, keeping the buffer.
+    pub fn take_corrupt(&mut self) -> Vec<u8> {
+        let frame = self.buf.as_slice().to_vec();
+        self.discard_frame();
+        frame
+    }
+
+    /// Drop the completed frame, keeping the buffer for the next frame.
+    pub fn discard_frame(&mut self) {
+        self.checksum_failed = false;
+        self.buf.clear();
+        self.state = RecvState::Unknown;
+    }
+
+    /// Drop any frame being received or completed and wait for the next sentinel.
+    pub fn abort(&mut self) {
+        self.reassembly_buf.clear();
+        self.escaped = false;
+        self.discard_frame();
+    }
+
+    /// Drop a partially received frame and wait for the next sentinel.
+    ///
+    /// A completed frame is kept.
+    pub fn resync(&mut self) {
+        if !self.is_frame_complete() {
+            if self.is_receiving() {
+                self.resyncs = self.resyncs.saturating_add(1);
+            }
+            self.checksum_failed = false;
+            self.escaped = false;
+            self.buf.clear();
+            self.reassembly_buf.clear();
+            self.state = RecvState::Unknown;
+        }
+    }
+
+    /// Take the bytes of a partially received frame, including earlier chunks.
+    pub fn take_partial(&mut self) -> Vec<u8> {
+        let mut partial = Vec::new();
+        partial.append(&mut self.reassembly_buf);
+        partial.append(&mut self.buf);
+        partial
+    }
+
+    /// The capacity of the receive buffer, in bytes.
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    /// Release excess capacity of the buffers.
+    pub fn shrink_to_fit(&mut self) {
+        self.buf.shrink_to_fit();
+        self.reassembly_buf.shrink_to_fit();
+    }
+
+    /// The capacity of all buffers, in bytes.
+    pub fn heap_bytes(&self) -> usize {
+        self.buf.capacity() + self.reassembly_buf.capacity() +
+            self.tag_bounds.capacity() * core::mem::size_of::<(u8, usize, usize)>()
+    }
+}
+
+/// Whether `total` bytes of a frame with `tag` are within its bounds, if any.
+///
+/// While `more` chunks follow, only the maximum applies.
+fn tag_length_ok(tag_bounds: &[(u8, usize, usize)], tag: u8, total: usize, more: bool) -> bool {
+    match tag_bounds.iter().find(|&&(t, _, _)| t == tag) {
+        Some(&(_, min, max)) => total <= max && (more || total >= min),
+        None => true,
+    }
+}
+
+/// The bytes a `FramedConnection` with default settings sends for `data`.
+///
+/// Returns an `Error` if `data` is too long for a frame.
+pub fn encode_frame(data: &[u8]) -> Result<Vec<u8>> {
+    encode_frame_with(&FrameConfig::new(), data)
+}
+
+/// The bytes a `FramedConnection` with the options `config` sends for `data`.
+///
+/// This runs the same encoder as sending over a serial device, so it can be
+/// used to check the wire format without one. With tagging enabled, the
+/// frame has tag 0, as with `FramedConnection::schedule_send()`. Returns an
+/// `Error` if `config` cannot send `data`.
+pub fn encode_frame_with(config: &FrameConfig, data: &[u8]) -> Result<Vec<u8>> {
+    let tag = if config.tagging { Some(0) } else { None };
+    let mut encoder = FrameEncoder::new(config, tag, data.to_vec())?;
+    let mut wire = Vec::with_capacity(data.len() + MAX_HEADER_LEN + 3);
+    while let Some(byte) = encoder.peek(config)? {
+        wire.push(byte);
+        encoder.advance(config)?;
+    }
+    Ok(wire)
+}
+
+/// Decode frames from bytes pushed one at a time.
+///
+/// This is the receive half of a `FramedConnection`, without a serial
+/// device. Bytes before a sentinel are skipped, as with a connection.
+pub struct FrameDecoder {
+    config: FrameConfig,
+    receiver: Receiver,
+}
+
+impl FrameDecoder {
+    /// Constructor, for frames sent by a `FramedConnection` with default settings.
+    pub fn new() -> FrameDecoder {
+        FrameDecoder::with_config(FrameConfig::new())
+    }
+
+    /// Create a `FrameDecoder` for frames sent with the options `config`.
+    ///
+    /// See `FramedConnection::frame_config()`.
+    pub fn with_config(config: FrameConfig) -> FrameDecoder {
+        FrameDecoder { config, receiver: Receiver::new() }
+    }
+
+    /// Decode one byte. Returns a frame if `byte` completed one.
+    ///
+    /// Returns an `Error` of kind `ErrorKind::ChecksumMismatch` if the
+    /// frame which `byte` completed has a wrong checksum, as
+    /// `FramedConnection::get_frame()` would. That frame is dropped.
+    pub fn push(&mut self, byte: u8) -> Result<Option<Vec<u8>>> {
+        if !self.receiver.push(&self.config, byte)? {
+            return Ok(None);
+        }
+        self.receiver.take_frame(false).map(|info| Some(info.data))
+    }
+
+    /// Whether a frame has started but is not yet complete.
+    pub fn is_receiving(&self) -> bool {
+        self.receiver.is_receiving()
+    }
+
+    /// The options frames are decoded with.
+    pub fn config(&self) -> &FrameConfig {
+        &self.config
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> FrameDecoder {
+        FrameDecoder::new()
+    }
+}
@@ -0,0 +1,150 @@
+use embedded_serial::{NonBlockingRx, NonBlockingTx};
+use byteorder::{ByteOrder, LittleEndian};
+use super::{Error, ErrorKind, FramedConnection, Result};
+
+#[cfg(feature = "collections")]
+use collections::vec::Vec;
+
+/// The fragment index and count before the data of each fragment.
+const FRAGMENT_HEADER_LEN: usize = 8;
+
+/// A payload being reassembled.
+struct Reassembly {
+    count: u32,
+    next: u32,
+    data: Vec<u8>,
+}
+
+/// Payloads larger than a frame over a `FramedConnection`.
+///
+/// `send_large()` splits a payload into fragments, each sent as a frame
+/// carrying its index, the number of fragments and up to `fragment_len`
+/// bytes of data. `poll_large()` joins them again. Fragments must arrive in
+/// order and without gaps, so the connection should have checksums
+/// enabled. Both ends must use a `FragmentedConnection`.
+pub struct FragmentedConnection<S>
+    where S: NonBlockingRx + NonBlockingTx,
+{
+    conn: FramedConnection<S>,
+    fragment_len: usize,
+    reassembly: Option<Reassembly>,
+    /// A payload completed while an error was returned.
+    ready: Option<Vec<u8>>,
+}
+
+impl<S> FragmentedConnection<S>
+    where S: NonBlockingRx + NonBlockingTx,
+{
+    /// Constructor. Takes ownership of the connection.
+    ///
+    /// Each fragment carries up to `fragment_len` bytes of the payload, at
+    /// least 1, and 8 bytes more are sent in each frame.
+    pub fn new(conn: FramedConnection<S>, fragment_len: usize) -> FragmentedConnection<S> {
+        FragmentedConnection {
+            conn,
+            fragment_len: core::cmp::max(1, fragment_len),
+            reassembly: None,
+            ready: None,
+        }
+    }
+
+    /// Borrow the connection.
+    pub fn get_ref(&self) -> &FramedConnection<S> {
+        &self.conn
+    }
+
+    /// Mutably borrow the connection.
+    ///
+    /// Sending or receiving frames on it directly confuses the peer.
+    pub fn get_mut(&mut self) -> &mut FramedConnection<S> {
+        &mut self.conn
+    }
+
+    /// Return the connection. A partially received payload is dropped.
+    pub fn into_inner(self) -> FramedConnection<S> {
+        self.conn
+    }
+
+    /// Schedule `data` to be sent as fragments.
+    ///
+    /// Returns an `Error` if the connection refuses a fragment, for example
+    /// because `fragment_len` is too large for its length field. Fragments
+    /// scheduled before the error are still sent.
+    pub fn send_large(&mut self, data: &[u8]) -> Result<()> {
+        let count = core::cmp::max(1, data.chunks(self.fragment_len).len());
+        if count > u32::MAX as usize {
+            return Err(Error::from_kind(ErrorKind::FrameTooLong));
+        }
+        let mut chunks = data.chunks(self.fragment_len);
+        for index in 0..count {
+            let chunk = chunks.next().unwrap_or(&[]);
+            let mut header = [0; FRAGMENT_HEADER_LEN];
+            LittleEndian::write_u32(&mut header[..4], index as u32);
+            LittleEndian::write_u32(&mut header[4..], count as u32);
+            let mut frame = Vec::with_capacity(FRAGMENT_HEADER_LEN + chunk.len());
+            frame.extend_from_slice(&header);
+            frame.extend_from_slice(chunk);
+            self.conn.schedule_send(frame)?;
+        }
+        Ok(())
+    }
+
+    /// Service the connection. Returns a completely received payload, if any.
+    ///
+    /// Returns an `Error` if a fragment is missing or out of order, and the
+    /// payload it belonged to is dropped. A following payload is received
+    /// normally.
+    pub fn poll_large(&mut self) -> Result<Option<Vec<u8>>> {
+        if let Some(data) = self.ready.take() {
+            return Ok(Some(data));
+        }
+        self.conn.tick()?;
+        while self.conn.frame_ready() {
+            let frame = self.conn.get_frame()?;
+            if let Some(data) = self._push(frame)? {
+                return Ok(Some(data));
+            }
+        }
+        Ok(None)
+    }
+
+    fn _push(&mut self, mut frame: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        if frame.len() < FRAGMENT_HEADER_LEN {
+            self.reassembly = None;
+            return Err(Error::with_detail(ErrorKind::InvalidFrame, "fragment header truncated".into()));
+        }
+        let index = LittleEndian::read_u32(&frame[..4]);
+        let count = LittleEndian::read_u32(&frame[4..FRAGMENT_HEADER_LEN]);
+        frame.drain(..FRAGMENT_HEADER_LEN);
+        let expected = self.reassembly.as_ref().map(|r| (r.next, r.count));
+        if index == 0 {
+            self.reassembly = Some(Reassembly { count, next: 0, data: Vec::new() });
+            if expected.is_some() {
+                // keep the new payload, but report the lost one
+                self.ready = self._append(frame);
+                return Err(Error::with_detail(ErrorKind::InvalidFrame, "fragments missing".into()));
+            }
+        } else if expected != Some((index, count)) {
+            self.reassembly = None;
+            return Err(Error::with_detail(ErrorKind::InvalidFrame, "fragment out of order".into()));
+        }
+        Ok(self._append(frame))
+    }
+
+    /// Add the next fragment. Returns the payload if it is complete.
+    fn _append(&mut self, mut frame: Vec<u8>) -> Option<Vec<u8>> {
+        let done = match self.reassembly {
+            Some(ref mut r) => {
+                r.data.append(&mut frame);
+                r.next = r.next.saturating_add(1);
+                r.next >= r.count
+            },
+            None => false,
+        };
+        if done {
+            self.reassembly.take().map(|r| r.data)
+        } else {
+            None
+        }
+    }
+}
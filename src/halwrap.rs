@@ -0,0 +1,57 @@
+use embedded_hal::serial;
+use nb;
+use super::embedded_serial;
+use super::{Error, ErrorKind};
+
+/// Implement the traits required for a `FramedConnection` based on
+/// `embedded_hal::serial::Read` and `Write`.
+///
+/// `nb::Error::WouldBlock` is reported as no byte being available, or the
+/// transmitter being full, so the connection tries again on the next
+/// `tick()`. Other errors of the driver become an `Error` of kind
+/// `ErrorKind::RxError` or `ErrorKind::TxError`.
+pub struct HalWrap<R, W> {
+    rx: R,
+    tx: W,
+}
+
+impl<R, W> HalWrap<R, W>
+    where R: serial::Read<u8>,
+          W: serial::Write<u8>,
+{
+    /// Constructor
+    pub fn new(rx: R, tx: W) -> HalWrap<R, W> {
+        HalWrap { rx, tx }
+    }
+
+    /// Return the receive and transmit halves.
+    pub fn into_inner(self) -> (R, W) {
+        (self.rx, self.tx)
+    }
+}
+
+impl<R, W> embedded_serial::NonBlockingRx for HalWrap<R, W>
+    where R: serial::Read<u8>,
+{
+    type Error=Error;
+    fn getc_try(&mut self) -> Result<Option<u8>, Self::Error> {
+        match self.rx.read() {
+            Ok(byte) => Ok(Some(byte)),
+            Err(nb::Error::WouldBlock) => Ok(None),
+            Err(nb::Error::Other(_)) => Err(Error::from_kind(ErrorKind::RxError)),
+        }
+    }
+}
+
+impl<R, W> embedded_serial::NonBlockingTx for HalWrap<R, W>
+    where W: serial::Write<u8>,
+{
+    type Error=Error;
+    fn putc_try(&mut self, ch: u8) -> Result<Option<u8>, Self::Error> {
+        match self.tx.write(ch) {
+            Ok(()) => Ok(Some(ch)),
+            Err(nb::Error::WouldBlock) => Ok(None),
+            Err(nb::Error::Other(_)) => Err(Error::from_kind(ErrorKind::TxError)),
+        }
+    }
+}
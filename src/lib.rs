@@ -80,8 +80,25 @@ extern crate collections;
 #[cfg(feature = "std")]
 extern crate serial;
 
+#[cfg(feature = "embedded-hal")]
+extern crate embedded_hal;
+
+#[cfg(feature = "embedded-hal")]
+extern crate nb;
+
+#[cfg(feature = "defmt")]
+extern crate defmt;
+
+#[cfg(feature = "tokio")]
+extern crate tokio_util;
+
+#[cfg(feature = "tokio")]
+extern crate bytes;
+
 #[cfg(feature = "std")]
 mod core {
+    pub use std::cmp;
+    pub use std::convert;
     pub use std::mem;
     pub use std::fmt;
     pub use std::result;
@@ -93,15 +110,79 @@ use byteorder::ByteOrder;
 #[cfg(feature = "collections")]
 use collections::vec::Vec;
 
+#[cfg(feature = "collections")]
+use collections::vec_deque::VecDeque;
+
+#[cfg(feature = "collections")]
+use collections::boxed::Box;
+
+/// Log a state transition with `defmt::trace!()`. Compiles to nothing
+/// without the `defmt` feature.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "defmt")]
+        defmt::trace!($($arg)*);
+    };
+}
+
 #[cfg(feature = "std")]
 mod serialwrap;
 
 #[cfg(feature = "std")]
 pub use serialwrap::SerialWrap;
 
+#[cfg(feature = "embedded-hal")]
+mod halwrap;
+
+#[cfg(feature = "embedded-hal")]
+pub use halwrap::HalWrap;
+
+mod split;
+
+pub use split::Split;
+
+mod translator;
+
+pub use translator::Translator;
+
+mod reliable;
+
+pub use reliable::ReliableConnection;
+
+mod fragment;
+
+pub use fragment::FragmentedConnection;
+
+mod slip;
+
+pub use slip::SlipConnection;
+
+mod codec;
+
+pub use codec::{encode_frame, encode_frame_with, FrameDecoder};
+
+use codec::{FrameEncoder, Receiver};
+
+#[cfg(feature = "tokio")]
+mod tokio_codec;
+
+#[cfg(feature = "tokio")]
+pub use tokio_codec::FramedCodec;
+
+mod builder;
+
+pub use builder::FramedConnectionBuilder;
+
+#[cfg(feature = "test-util")]
+mod testutil;
+
+#[cfg(feature = "test-util")]
+pub use testutil::{loopback, BenchSerial, LoopbackSerial};
+
 #[cfg(not(feature = "std"))]
 use collections::String;
 
+use core::convert::TryFrom;
 use core::fmt::Display;
 
 #[cfg(not(feature = "std"))]
@@ -110,6 +191,12 @@ use core::fmt::Debug;
 #[cfg(feature = "std")]
 use std::error::Error as StdError;
 
+#[cfg(feature = "std")]
+use std::collections::VecDeque;
+
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
 /// A replacement for std::error::Error
 #[cfg(not(feature = "std"))]
 pub trait StdError: Debug + Display {
@@ -124,35 +211,426 @@ pub trait StdError: Debug + Display {
     fn cause(&self) -> Option<&StdError> { None }
 }
 
+/// Line conditions a serial device may report in addition to received bytes.
+///
+/// Every method has a default for a device which cannot detect the
+/// condition, so implementors only override what their hardware supports.
+/// Use `FramedConnection::tick_with_line_status()` to act on them.
+pub trait LineStatus {
+    /// Return `true` if a BREAK was received since the last call.
+    fn take_break(&mut self) -> bool { false }
+    /// Return `false` while hardware flow control forbids transmitting.
+    fn is_clear_to_send(&mut self) -> bool { true }
+    /// Return `true` if a byte written since the last call read back
+    /// differently, because another node transmitted at the same time.
+    #[cfg(feature = "collision-detection")]
+    fn last_tx_collided(&mut self) -> bool { false }
+}
+
+/// A transmitter whose `NonBlockingTx::puts_try()` writes several bytes in one call.
+///
+/// The default `puts_try()` calls `putc_try()` for each byte. Devices
+/// which override it with a real bulk write, for example one system call,
+/// implement this trait so `FramedConnection::set_bulk_write()` can use it.
+pub trait NonBlockingTxBulk: NonBlockingTx {}
+
+/// Writes with `NonBlockingTx::puts_try()`, returning the number of bytes
+/// written before an error as the error.
+type BulkWrite<S> = fn(&mut S, &[u8]) -> core::result::Result<usize, usize>;
+
+/// A reversible transform applied to every byte on the wire, such as a cipher.
+///
+/// Outgoing bytes are encoded after framing and incoming bytes are decoded
+/// before framing is parsed, so the sentinel and header are transformed too.
+/// Both ends must use the same transform and key. The transform may keep
+/// state, as for a stream cipher; each byte is encoded and decoded once.
+pub trait ByteTransform {
+    /// Transform an outgoing byte.
+    fn encode(&mut self, byte: u8) -> u8;
+    /// Reverse `encode` for an incoming byte.
+    fn decode(&mut self, byte: u8) -> u8;
+}
+
+/// A connection exchanging whole frames, implemented by `FramedConnection`.
+///
+/// Code written against this trait can run over another framing, or over a
+/// test double, without changes.
+pub trait FrameTransport {
+    /// Schedule a frame to be sent by subsequent calls to `tick()`.
+    fn schedule_frame(&mut self, frame: Vec<u8>) -> Result<()>;
+    /// Take a received frame, if one is ready. Does not service the connection.
+    fn try_get_frame(&mut self) -> Result<Option<Vec<u8>>>;
+    /// Service the connection.
+    fn tick(&mut self) -> Result<TickProgress>;
+}
+
+/// The default state of the collision backoff generator.
+#[cfg(feature = "collision-detection")]
+const BACKOFF_SEED: u32 = 0x9E37_79B9;
+
 /// A marker which appears only rarely in stream, used to catch frame start.
 pub const SENTINEL: u8 = 0xFF;
 
-struct HeaderState {
-    bytes: [u8; 2],
-    index: usize,
+/// The escape byte used by `FramedConnection::set_escaping()`.
+///
+/// A sentinel or `ESC` byte in a frame is sent as `ESC` followed by the
+/// byte XOR `ESC`.
+pub const ESC: u8 = 0xFE;
+
+/// The longest magic sequence accepted by `FramedConnection::set_magic()`.
+pub const MAX_MAGIC_LEN: usize = 4;
+
+/// The order in which `get_frame()` returns frames when several are waiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueOrder {
+    /// Oldest first. The default.
+    Fifo,
+    /// Newest first, for data where the latest value matters most.
+    Lifo,
+    /// Return only the newest frame, discarding older waiting frames.
+    CoalesceLatest,
+}
+
+/// The size of the length field of `FramingMode::LengthPrefixed` frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthWidth {
+    /// 1 byte, for frames of up to 255 bytes.
+    U8,
+    /// 2 bytes, for frames of up to 65535 bytes. The default.
+    U16,
+    /// 4 bytes, for frames of up to 4294967295 bytes.
+    U32,
+    /// A LEB128 varint of 1 to 5 bytes, for frames of up to 4294967295 bytes.
+    ///
+    /// Each byte carries 7 bits of the length, least significant first, and
+    /// has the high bit set if more bytes follow. Lengths below 128 take a
+    /// single byte. The byte order set with `set_endianness()` is ignored.
+    Varint,
+}
+
+/// The longest varint length field.
+const MAX_VARINT_LEN: usize = 5;
+
+/// Set in a varint byte if further bytes follow.
+const VARINT_MORE: u8 = 0x80;
+
+impl LengthWidth {
+    /// The number of bytes in the length field, the largest for `Varint`.
+    fn len(&self) -> usize {
+        match *self {
+            LengthWidth::U8 => 1,
+            LengthWidth::U16 => 2,
+            LengthWidth::U32 => 4,
+            LengthWidth::Varint => MAX_VARINT_LEN,
+        }
+    }
+
+    /// The number of bytes in the length field when holding `value`.
+    fn encoded_len(&self, value: usize) -> usize {
+        match *self {
+            LengthWidth::Varint => {
+                let mut n = 1;
+                let mut rest = value >> 7;
+                while rest > 0 {
+                    n += 1;
+                    rest >>= 7;
+                }
+                n
+            },
+            _ => self.len(),
+        }
+    }
+
+    /// The largest length the field holds.
+    fn max(&self) -> u64 {
+        match *self {
+            LengthWidth::U8 => u64::from(u8::MAX),
+            LengthWidth::U16 => u64::from(u16::MAX),
+            LengthWidth::U32 | LengthWidth::Varint => u64::from(u32::MAX),
+        }
+    }
+
+    /// Write `value`, which must not exceed `max()`, to the start of `buf`.
+    ///
+    /// Returns the number of bytes written.
+    fn write(&self, buf: &mut [u8], value: usize, endianness: Endianness) -> Result<usize> {
+        let len = self.encoded_len(value);
+        let field = match buf.get_mut(..len) {
+            Some(field) => field,
+            None => return Err(Error::with_detail(ErrorKind::Internal, "length field out of range".into())),
+        };
+        match (*self, endianness) {
+            (LengthWidth::U8, _) => {
+                if let Some(b) = field.first_mut() {
+                    *b = value as u8;
+                }
+            },
+            (LengthWidth::Varint, _) => {
+                let mut rest = value;
+                for b in field.iter_mut() {
+                    *b = (rest & 0x7F) as u8;
+                    rest >>= 7;
+                    if rest > 0 {
+                        *b |= VARINT_MORE;
+                    }
+                }
+            },
+            (LengthWidth::U16, Endianness::Little) => byteorder::LittleEndian::write_u16(field, value as u16),
+            (LengthWidth::U16, Endianness::Big) => byteorder::BigEndian::write_u16(field, value as u16),
+            (LengthWidth::U32, Endianness::Little) => byteorder::LittleEndian::write_u32(field, value as u32),
+            (LengthWidth::U32, Endianness::Big) => byteorder::BigEndian::write_u32(field, value as u32),
+        }
+        Ok(len)
+    }
+
+    /// Read a length from the start of `buf`.
+    ///
+    /// Returns an `Error` of kind `ErrorKind::FrameTooLong` if the length
+    /// does not fit in a `usize`, as on 16 bit targets.
+    fn read(&self, buf: &[u8], endianness: Endianness) -> Result<usize> {
+        let field = match buf.get(..self.len()) {
+            Some(field) => field,
+            // a varint may end before `len()`
+            None if *self == LengthWidth::Varint => buf,
+            None => return Err(Error::with_detail(ErrorKind::Internal, "length field out of range".into())),
+        };
+        let value = match (*self, endianness) {
+            (LengthWidth::U8, _) => field.first().map_or(0, |&b| u64::from(b)),
+            (LengthWidth::Varint, _) => {
+                let mut value = 0;
+                for (i, &b) in field.iter().enumerate() {
+                    value |= u64::from(b & !VARINT_MORE) << (7 * i);
+                    if b & VARINT_MORE == 0 {
+                        break;
+                    }
+                }
+                value
+            },
+            (LengthWidth::U16, Endianness::Little) => u64::from(byteorder::LittleEndian::read_u16(field)),
+            (LengthWidth::U16, Endianness::Big) => u64::from(byteorder::BigEndian::read_u16(field)),
+            (LengthWidth::U32, Endianness::Little) => u64::from(byteorder::LittleEndian::read_u32(field)),
+            (LengthWidth::U32, Endianness::Big) => u64::from(byteorder::BigEndian::read_u32(field)),
+        };
+        usize::try_from(value).map_err(|_| Error::from_kind(ErrorKind::FrameTooLong))
+    }
+}
+
+/// The byte order of the length field of `FramingMode::LengthPrefixed`
+/// frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    /// Least significant byte first. The default.
+    Little,
+    /// Most significant byte first, also called network byte order.
+    Big,
 }
 
-struct DataState {
-    length: usize,
+/// `Endianness::Little`, for naming the byte order at the call site, as in
+/// `FramedConnectionBuilder::new().endian(LittleEndian)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LittleEndian;
+
+/// `Endianness::Big`, for naming the byte order at the call site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BigEndian;
+
+/// The byte order of the target, as `LittleEndian` or `BigEndian`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NativeEndian;
+
+impl From<LittleEndian> for Endianness {
+    fn from(_: LittleEndian) -> Endianness {
+        Endianness::Little
+    }
 }
 
-enum RecvState {
-    Unknown,
-    Header(HeaderState),
-    Data(DataState),
+impl From<BigEndian> for Endianness {
+    fn from(_: BigEndian) -> Endianness {
+        Endianness::Big
+    }
 }
 
-enum WhatNext {
-    Sentinel,
-    Header,
-    Data,
+impl From<NativeEndian> for Endianness {
+    fn from(_: NativeEndian) -> Endianness {
+        if cfg!(target_endian = "big") { Endianness::Big } else { Endianness::Little }
+    }
+}
+
+/// How frames are delimited on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramingMode {
+    /// A sentinel, a length, then the data. The default.
+    ///
+    /// The length field is 2 bytes, little endian, unless set otherwise with
+    /// `FramedConnection::set_length_width()` and
+    /// `FramedConnection::set_endianness()`.
+    LengthPrefixed,
+    /// A sentinel followed by exactly this many data bytes, with no length.
+    ///
+    /// Every frame sent must have this length.
+    FixedSize(usize),
+}
+
+/// The options which decide how frames look on the wire.
+///
+/// Every `FramedConnection` keeps one, returned by
+/// `FramedConnection::frame_config()` and changed by the setters of the
+/// same names. `encode_frame_with()` and `FrameDecoder::with_config()` use
+/// one to encode and decode frames without a serial device, so they can
+/// talk to a connection with any options. Both ends of a link must use
+/// equal options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameConfig {
+    sentinel: u8,
+    accept_sentinels: Vec<u8>,
+    magic: [u8; MAX_MAGIC_LEN],
+    magic_len: usize,
+    sentinel_is_idle: bool,
+    framing_mode: FramingMode,
+    length_width: LengthWidth,
+    endianness: Endianness,
+    tagging: bool,
+    checksum: bool,
+    escaping: bool,
+    auto_chunk: Option<usize>,
+}
+
+impl FrameConfig {
+    /// The options of `FramedConnection::new()`.
+    pub fn new() -> FrameConfig {
+        FrameConfig {
+            sentinel: SENTINEL,
+            accept_sentinels: Vec::new(),
+            magic: [0; MAX_MAGIC_LEN],
+            magic_len: 0,
+            sentinel_is_idle: false,
+            framing_mode: FramingMode::LengthPrefixed,
+            length_width: LengthWidth::U16,
+            endianness: Endianness::Little,
+            tagging: false,
+            checksum: false,
+            escaping: false,
+            auto_chunk: None,
+        }
+    }
+
+    /// See `FramedConnection::set_sentinel()`.
+    pub fn set_sentinel(&mut self, sentinel: u8) -> Result<()> {
+        if self.escaping && sentinel == ESC {
+            return Err(Error::with_detail(ErrorKind::InvalidFrame, "sentinel is ESC".into()));
+        }
+        self.sentinel = sentinel;
+        Ok(())
+    }
+
+    /// See `FramedConnection::set_magic()`.
+    pub fn set_magic(&mut self, magic: &[u8]) -> Result<()> {
+        match self.magic.get_mut(..magic.len()) {
+            Some(dst) => dst.copy_from_slice(magic),
+            None => return Err(Error::with_detail(ErrorKind::InvalidFrame, "magic too long".into())),
+        }
+        self.magic_len = magic.len();
+        Ok(())
+    }
+
+    /// See `FramedConnection::set_accept_sentinels()`.
+    pub fn set_accept_sentinels(&mut self, sentinels: &[u8]) -> Result<()> {
+        if self.escaping && sentinels.contains(&ESC) {
+            return Err(Error::with_detail(ErrorKind::InvalidFrame, "sentinel is ESC".into()));
+        }
+        self.accept_sentinels = sentinels.to_vec();
+        Ok(())
+    }
+
+    /// See `FramedConnection::set_sentinel_is_idle()`.
+    pub fn set_sentinel_is_idle(&mut self, sentinel_is_idle: bool) {
+        self.sentinel_is_idle = sentinel_is_idle;
+    }
+
+    /// See `FramedConnection::set_framing_mode()`.
+    pub fn set_framing_mode(&mut self, mode: FramingMode) {
+        self.framing_mode = mode;
+    }
+
+    /// See `FramedConnection::set_length_width()`.
+    pub fn set_length_width(&mut self, width: LengthWidth) {
+        self.length_width = width;
+    }
+
+    /// See `FramedConnection::set_tagging()`.
+    pub fn set_tagging(&mut self, enabled: bool) {
+        self.tagging = enabled;
+    }
+
+    /// See `FramedConnection::set_endianness()`.
+    pub fn set_endianness<E: Into<Endianness>>(&mut self, endianness: E) {
+        self.endianness = endianness.into();
+    }
+
+    /// See `FramedConnection::set_auto_chunk()`.
+    pub fn set_auto_chunk(&mut self, max_chunk: Option<usize>) {
+        self.auto_chunk = max_chunk.map(|n| core::cmp::max(1, core::cmp::min(n, u16::MAX as usize)));
+    }
+
+    /// See `FramedConnection::set_checksum()`.
+    pub fn set_checksum(&mut self, enabled: bool) {
+        self.checksum = enabled;
+    }
+
+    /// See `FramedConnection::set_escaping()`.
+    pub fn set_escaping(&mut self, enabled: bool) -> Result<()> {
+        if enabled && (self.sentinel == ESC || self.accept_sentinels.contains(&ESC)) {
+            return Err(Error::with_detail(ErrorKind::InvalidFrame, "sentinel is ESC".into()));
+        }
+        self.escaping = enabled;
+        Ok(())
+    }
+
+    /// Whether `byte` starts a frame on receive.
+    fn is_sentinel(&self, byte: u8) -> bool {
+        if self.accept_sentinels.is_empty() {
+            byte == self.sentinel
+        } else {
+            self.accept_sentinels.contains(&byte)
+        }
+    }
+
+    /// Whether `byte` is escaped after the sentinel when escaping is enabled.
+    fn is_special(&self, byte: u8) -> bool {
+        byte == ESC || byte == self.sentinel || self.accept_sentinels.contains(&byte)
+    }
+
+    /// The magic sent after the sentinel.
+    fn magic(&self) -> &[u8] {
+        self.magic.get(..self.magic_len).unwrap_or(&[])
+    }
+}
+
+impl Default for FrameConfig {
+    fn default() -> FrameConfig {
+        FrameConfig::new()
+    }
 }
 
-struct SendingState{
-    what_next: WhatNext,
-    index: usize,
-    header_bytes: [u8; 2],
-    frame: Vec<u8>,
+/// A frame being sent.
+struct SendingState {
+    encoder: FrameEncoder,
+    /// An encoded byte the transmitter did not yet accept.
+    pending: Option<u8>,
+    /// The length of the wire capture when the current chunk started.
+    #[cfg(all(feature = "capture", feature = "collision-detection"))]
+    capture_start: usize,
+}
+
+impl SendingState {
+    fn new(encoder: FrameEncoder) -> SendingState {
+        SendingState {
+            encoder,
+            pending: None,
+            #[cfg(all(feature = "capture", feature = "collision-detection"))]
+            capture_start: 0,
+        }
+    }
 }
 
 enum SendState {
@@ -166,24 +644,326 @@ pub struct TickProgress {
     pub recv_is_done: bool,
     /// State of ongoing send.
     pub send_is_done: bool,
+    /// Whether more consecutive ticks than the idle tick warning limit
+    /// made no progress. See `FramedConnection::set_idle_tick_warn_limit()`.
+    pub idle_warning: bool,
+    /// Bytes accepted by the serial device during this tick, including framing.
+    pub bytes_sent: usize,
+    /// Bytes read from the serial device during this tick, including framing and noise.
+    pub bytes_received: usize,
 }
 
-/// Error type.
+/// A received frame along with information about how it was framed.
+#[derive(Debug)]
+pub struct FrameInfo {
+    /// The frame payload.
+    pub data: Vec<u8>,
+    /// The sentinel which started this frame.
+    pub sentinel: u8,
+    /// Whether this frame is the end-of-transmission marker.
+    pub is_eot: bool,
+    /// The position of this frame among all frames received, starting at 0.
+    ///
+    /// This is assigned locally and is not sent on the wire.
+    pub index: u64,
+    /// The tag sent along with the frame, if tagging is enabled.
+    ///
+    /// See `FramedConnection::set_tagging()`.
+    pub tag: Option<u8>,
+    /// The CRC-16 received after the frame, if checksums are enabled.
+    ///
+    /// This matched the computed checksum, as frames with a wrong checksum
+    /// are dropped. For a chunked frame, this is the checksum of the last
+    /// chunk. See `FramedConnection::set_checksum()`.
+    pub crc: Option<u16>,
+}
+
+/// A received frame or a receive error, returned by `FramedConnection::drain_outcomes()`.
+#[derive(Debug)]
+pub enum FrameOutcome {
+    /// A frame received intact.
+    Good(FrameInfo),
+    /// A receive error, with the bytes of the frame it dropped, if any.
+    ///
+    /// The kind is `ErrorKind::ChecksumMismatch` along with the corrupt
+    /// frame, or `ErrorKind::RxNoise` or `ErrorKind::RxError` without bytes.
+    Error(ErrorKind, Vec<u8>),
+}
+
+/// A frame whose sending was interrupted, returned by `FramedConnection::finalize()`.
+#[derive(Debug)]
+pub struct PartialSend {
+    /// The whole frame payload.
+    pub frame: Vec<u8>,
+    /// The number of payload bytes already accepted by the serial device.
+    ///
+    /// These are gone; `remainder()` returns the rest.
+    pub bytes_sent: usize,
+}
+
+impl PartialSend {
+    /// The payload bytes which were not yet accepted by the serial device.
+    pub fn remainder(&self) -> &[u8] {
+        self.frame.get(self.bytes_sent..).unwrap_or(&[])
+    }
+}
+
+/// Undelivered data returned by `FramedConnection::finalize()`.
 #[derive(Debug)]
+pub struct FinalState {
+    /// The frame being sent, if any.
+    pub unsent: Option<PartialSend>,
+    /// Frames queued behind `unsent`, in the order scheduled.
+    pub queued: Vec<Vec<u8>>,
+    /// Received frames not yet taken with `get_frame()`, oldest first.
+    pub received: Vec<FrameInfo>,
+    /// The payload bytes of a partially received frame.
+    pub partial_recv: Vec<u8>,
+}
+
+/// Frame rates over the window set by `FramedConnection::set_throughput_window()`.
+///
+/// Frames sent and frames received are both counted.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub struct Throughput {
+    /// Frames per second.
+    pub frames_per_sec: f64,
+    /// Payload bytes per second.
+    pub bytes_per_sec: f64,
+}
+
+/// The direction of a frame passed to a frame tee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// A frame which was sent.
+    Tx,
+    /// A frame which was received.
+    Rx,
+}
+
+/// A callback receiving a copy of every frame sent and received.
+pub type FrameTee = Box<dyn FnMut(Direction, &[u8]) + Send>;
+
+/// The overall verdict of `FramedConnection::health()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkStatus {
+    /// Frames are arriving without trouble.
+    Healthy,
+    /// Frames are arriving, but the receiver often loses sync.
+    Degraded,
+    /// No frame was received for a while.
+    Stale,
+    /// No frame was received for a long time.
+    Dead,
+}
+
+/// Thresholds used by `FramedConnection::health()` to derive a `LinkStatus`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthThresholds {
+    /// Resyncs per received frame above which the link is `Degraded`.
+    pub degraded_resync_rate: f64,
+    /// Checksum errors per received frame above which the link is `Degraded`.
+    pub degraded_checksum_error_rate: f64,
+    /// Ticks without a received frame after which the link is `Stale`.
+    pub stale_ticks: u64,
+    /// Ticks without a received frame after which the link is `Dead`.
+    pub dead_ticks: u64,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> HealthThresholds {
+        HealthThresholds {
+            degraded_resync_rate: 0.1,
+            degraded_checksum_error_rate: 0.01,
+            stale_ticks: 1_000,
+            dead_ticks: 10_000,
+        }
+    }
+}
+
+/// Traffic counters returned by `FramedConnection::stats()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Bytes accepted by the serial device, including framing.
+    pub bytes_sent: u64,
+    /// Bytes read from the serial device, including framing and noise.
+    pub bytes_received: u64,
+    /// Frames completely sent.
+    pub frames_sent: u64,
+    /// Frames completely received.
+    pub frames_received: u64,
+    /// Bytes discarded while waiting for a sentinel.
+    pub discarded_bytes: u64,
+}
+
+/// A summary of link health returned by `FramedConnection::health()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkHealth {
+    /// The overall verdict.
+    pub status: LinkStatus,
+    /// Resyncs per received frame.
+    pub resync_rate: f64,
+    /// Frames with a wrong checksum per received frame.
+    pub checksum_error_rate: f64,
+    /// Ticks since the last frame was received, or since the connection
+    /// was created if none was.
+    pub ticks_since_last_frame: u64,
+    /// Received frames waiting for `get_frame()`.
+    pub rx_queue_depth: usize,
+    /// Frames waiting to be sent, including one being sent.
+    pub tx_queue_depth: usize,
+}
+
+/// The phase of receiving, returned by `FramedConnection::recv_progress()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvProgress {
+    /// Waiting for a sentinel.
+    Idle,
+    /// Receiving the tag or length after a sentinel.
+    Header,
+    /// Receiving the payload of a frame or chunk.
+    Data {
+        /// Payload bytes received.
+        received: usize,
+        /// The declared payload length.
+        length: usize,
+    },
+    /// Receiving the checksum after the payload.
+    Checksum,
+}
+
+impl Display for RecvProgress {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match *self {
+            RecvProgress::Idle => write!(f, "idle"),
+            RecvProgress::Header => write!(f, "receiving header"),
+            RecvProgress::Data { received, length } => write!(f, "received {} of {} bytes", received, length),
+            RecvProgress::Checksum => write!(f, "receiving checksum"),
+        }
+    }
+}
+
+/// The progress of sending a frame, returned by `FramedConnection::send_progress()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendProgress {
+    /// Payload bytes accepted by the serial device.
+    pub sent: usize,
+    /// The payload length of the frame.
+    pub total: usize,
+}
+
+impl Display for SendProgress {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "sent {} of {} bytes", self.sent, self.total)
+    }
+}
+
+/// The kind of an `Error`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ErrorKind {
+    /// The frame is longer than the framing allows.
+    FrameTooLong,
+    /// The frame does not have the size required by `FramingMode::FixedSize`.
+    WrongFrameSize,
+    /// The frame cannot be sent with the current settings.
+    InvalidFrame,
+    /// Sending is disabled by `set_listen_only()`.
+    ListenOnly,
+    /// No complete frame is available.
+    FrameNotAvailable,
+    /// The serial device failed to transmit.
+    TxError,
+    /// The serial device failed to receive.
+    RxError,
+    /// A received frame had a wrong checksum.
+    ChecksumMismatch,
+    /// The buffer passed to `recv_into()` is too small for the frame.
+    BufferTooSmall,
+    /// An operation did not complete in time.
+    Timeout,
+    /// A partially received frame was dropped because its bytes stopped
+    /// arriving. See `FramedConnection::set_recv_timeout()`.
+    FrameTimeout,
+    /// More bytes than allowed arrived before a sentinel. See
+    /// `FramedConnection::set_rx_noise_limit()`.
+    RxNoise,
+    /// A frame is already being sent, or too many wait for acknowledgement.
+    /// See `FramedConnection::try_schedule_send()` and
+    /// `ReliableConnection::send_reliable()`.
+    Busy,
+    /// Sending did not complete within the allowed ticks. See
+    /// `FramedConnection::flush_with_limit()`.
+    WouldBlock,
+    /// A bug in this crate.
+    Internal,
+    /// Any other error, such as one created with `Error::new()`.
+    Other,
+}
+
+impl ErrorKind {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            ErrorKind::FrameTooLong => "frame data too long",
+            ErrorKind::WrongFrameSize => "frame data does not match fixed size",
+            ErrorKind::InvalidFrame => "frame cannot be sent with the current settings",
+            ErrorKind::ListenOnly => "cannot send in listen-only mode",
+            ErrorKind::FrameNotAvailable => "frame not available",
+            ErrorKind::TxError => "error during putc_try()",
+            ErrorKind::RxError => "error during getc_try()",
+            ErrorKind::ChecksumMismatch => "frame checksum mismatch",
+            ErrorKind::BufferTooSmall => "buffer too small for frame",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::FrameTimeout => "partial frame timed out",
+            ErrorKind::RxNoise => "too many bytes before a sentinel",
+            ErrorKind::Busy => "a frame is already being sent",
+            ErrorKind::WouldBlock => "sending did not complete",
+            ErrorKind::Internal => "internal error",
+            ErrorKind::Other => "error",
+        }
+    }
+}
+
+/// Error type.
+///
+/// Errors are equal if they have the same kind and detail.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Error {
-    descr: String,
+    kind: ErrorKind,
+    detail: Option<String>,
 }
 
 impl Error {
-    /// create a new Error
+    /// create a new Error of kind `ErrorKind::Other`
     pub fn new(s: String) -> Error {
-        Error { descr: s }
+        Error::with_detail(ErrorKind::Other, s)
+    }
+
+    /// create a new Error of the given kind
+    pub fn from_kind(kind: ErrorKind) -> Error {
+        Error { kind, detail: None }
+    }
+
+    /// create a new Error of the given kind with more detail
+    pub fn with_detail(kind: ErrorKind, detail: String) -> Error {
+        Error { kind, detail: Some(detail) }
+    }
+
+    /// The kind of this error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// More detail about this error, if any.
+    pub fn detail(&self) -> Option<&str> {
+        self.detail.as_deref()
     }
 }
 
 impl StdError for Error {
     fn description(&self) -> &str {
-        return &self.descr;
+        self.kind.as_str()
     }
 }
 
@@ -191,7 +971,36 @@ type Result<T> = core::result::Result<T,Error>;
 
 impl Display for Error {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
-        write!(f, "Error: {}", self.description())
+        match (self.kind, self.detail()) {
+            (ErrorKind::Other, Some(detail)) => write!(f, "Error: {}", detail),
+            (kind, Some(detail)) => write!(f, "Error: {}: {}", kind.as_str(), detail),
+            (kind, None) => write!(f, "Error: {}", kind.as_str()),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        match self.detail() {
+            Some(detail) => defmt::write!(f, "Error: {}: {=str}", self.kind, detail),
+            None => defmt::write!(f, "Error: {}", self.kind),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> std::io::Error {
+        let kind = match err.kind() {
+            ErrorKind::FrameTooLong | ErrorKind::WrongFrameSize | ErrorKind::InvalidFrame =>
+                std::io::ErrorKind::InvalidInput,
+            ErrorKind::ChecksumMismatch | ErrorKind::RxNoise => std::io::ErrorKind::InvalidData,
+            ErrorKind::Timeout | ErrorKind::FrameTimeout => std::io::ErrorKind::TimedOut,
+            ErrorKind::Busy | ErrorKind::WouldBlock => std::io::ErrorKind::WouldBlock,
+            _ => std::io::ErrorKind::Other,
+        };
+        std::io::Error::new(kind, err)
     }
 }
 
@@ -202,62 +1011,892 @@ pub struct FramedConnection<S>
     where S : NonBlockingRx + NonBlockingTx,
 {
     serial: S,
-    recv_buf: Vec<u8>,
-    recv_state: RecvState,
+    config: FrameConfig,
+    receiver: Receiver,
     send_state: SendState,
+    tx_chunk_yield: Option<usize>,
+    listen_only: bool,
+    eot_marker: Option<Vec<u8>>,
+    recv_paused: bool,
+    n_frames_sent: u64,
+    n_frames_received: u64,
+    breaks_detected: u64,
+    bulk_write: Option<BulkWrite<S>>,
+    recv_timeout: Option<u64>,
+    /// The `now` of the last `tick_with_now()` which saw a received byte.
+    last_rx_now: u64,
+    /// `bytes_received` at that `tick_with_now()`.
+    rx_bytes_at_now: u64,
+    recv_queue: VecDeque<FrameInfo>,
+    in_band_errors: bool,
+    /// Receive errors for `drain_outcomes()`, with the number of frames received before each.
+    rejects: VecDeque<(u64, ErrorKind, Vec<u8>)>,
+    send_queue: VecDeque<FrameEncoder>,
+    queue_order: QueueOrder,
+    transform: Option<Box<dyn ByteTransform + Send>>,
+    frame_tee: Option<FrameTee>,
+    on_send_idle: Option<Box<dyn FnMut() + Send>>,
+    retain_recv_buffer: bool,
+    /// The buffer of a sent frame, reused by `schedule_send_slice()`.
+    /// `None` until that is first called.
+    spare_send_buf: Option<Vec<u8>>,
+    ticks: u64,
+    tx_inter_frame_gap_ticks: u64,
+    turnaround_ticks: u64,
+    bytes_sent: u64,
+    idle_ticks: u64,
+    idle_tick_warn_limit: Option<u64>,
+    last_rx_tick: Option<u64>,
+    last_tx_done_tick: Option<u64>,
+    last_rx_frame_tick: u64,
+    health_thresholds: HealthThresholds,
+    bytes_received: u64,
+    /// The counters at the last `reset_stats()`.
+    stats_base: Stats,
+    /// The byte budget and its window in ticks.
+    tx_rate_limit: Option<(usize, u64)>,
+    tx_window_start: u64,
+    tx_window_bytes: usize,
+    #[cfg(feature = "std")]
+    throughput_window: Duration,
+    #[cfg(feature = "std")]
+    throughput_events: VecDeque<(Instant, usize)>,
+    #[cfg(feature = "capture")]
+    tx_capture: Vec<u8>,
+    #[cfg(feature = "capture")]
+    last_tx_wire: Option<Vec<u8>>,
+    #[cfg(feature = "collision-detection")]
+    collisions: u64,
+    #[cfg(feature = "collision-detection")]
+    collision_backoff_max: u64,
+    #[cfg(feature = "collision-detection")]
+    backoff_rng: u32,
+    #[cfg(feature = "collision-detection")]
+    tx_backoff_until: u64,
+    /// Polls the serial device for a collision after each write, set during
+    /// `tick_with_line_status()`.
+    #[cfg(feature = "collision-detection")]
+    collision_check: Option<fn(&mut S) -> bool>,
 }
 
 impl<S> FramedConnection<S>
     where S : NonBlockingRx + NonBlockingTx,
 {
+    /// Create a new `FramedConnection` which drops frames longer than `max` bytes.
+    ///
+    /// A frame whose header declares a greater length is discarded as soon
+    /// as the header is received and counted in `resync_count()`, so a
+    /// corrupt header cannot make the receive buffer grow beyond `max`. With
+    /// `new()`, any length the length field holds is accepted.
+    pub fn new_with_max_recv_len(s: S, max: usize) -> FramedConnection<S> {
+        let mut conn = FramedConnection::new(s);
+        conn.receiver.max_recv_len = Some(max);
+        conn
+    }
+
+    /// Create a new `FramedConnection` whose receive buffer holds `cap` bytes.
+    ///
+    /// Frames up to `cap` bytes are then received without allocating
+    /// for the buffer. See `set_retain_recv_buffer()` to keep it across
+    /// frames.
+    pub fn new_with_capacity(s: S, cap: usize) -> FramedConnection<S> {
+        let mut conn = FramedConnection::new(s);
+        conn.receiver = Receiver::with_capacity(cap);
+        conn
+    }
+
     /// Create a new `FramedConnection`. Takes ownership of the serial device.
     pub fn new(s:S) -> FramedConnection<S> {
         FramedConnection {
             serial:s,
-            recv_buf: Vec::new(),
-            recv_state: FramedConnection::<S>::_start_recv_state(),
+            config: FrameConfig::new(),
+            receiver: Receiver::new(),
             send_state: FramedConnection::<S>::_start_send_state(),
+            tx_chunk_yield: None,
+            listen_only: false,
+            eot_marker: None,
+            recv_paused: false,
+            n_frames_sent: 0,
+            n_frames_received: 0,
+            breaks_detected: 0,
+            bulk_write: None,
+            recv_timeout: None,
+            last_rx_now: 0,
+            rx_bytes_at_now: 0,
+            recv_queue: VecDeque::new(),
+            in_band_errors: false,
+            rejects: VecDeque::new(),
+            send_queue: VecDeque::new(),
+            queue_order: QueueOrder::Fifo,
+            transform: None,
+            frame_tee: None,
+            on_send_idle: None,
+            retain_recv_buffer: false,
+            spare_send_buf: None,
+            ticks: 0,
+            tx_inter_frame_gap_ticks: 0,
+            turnaround_ticks: 0,
+            bytes_sent: 0,
+            idle_ticks: 0,
+            idle_tick_warn_limit: None,
+            last_rx_tick: None,
+            last_tx_done_tick: None,
+            last_rx_frame_tick: 0,
+            health_thresholds: HealthThresholds::default(),
+            bytes_received: 0,
+            stats_base: Stats::default(),
+            tx_rate_limit: None,
+            tx_window_start: 0,
+            tx_window_bytes: 0,
+            #[cfg(feature = "std")]
+            throughput_window: Duration::from_secs(5),
+            #[cfg(feature = "std")]
+            throughput_events: VecDeque::new(),
+            #[cfg(feature = "capture")]
+            tx_capture: Vec::new(),
+            #[cfg(feature = "capture")]
+            last_tx_wire: None,
+            #[cfg(feature = "collision-detection")]
+            collisions: 0,
+            #[cfg(feature = "collision-detection")]
+            collision_backoff_max: 16,
+            #[cfg(feature = "collision-detection")]
+            backoff_rng: BACKOFF_SEED,
+            #[cfg(feature = "collision-detection")]
+            tx_backoff_until: 0,
+            #[cfg(feature = "collision-detection")]
+            collision_check: None,
             }
     }
 
-    fn _start_recv_state() -> RecvState {
-        RecvState::Unknown
+    /// The options which decide how frames look on the wire.
+    ///
+    /// The setters for framing, such as `set_checksum()`, change these.
+    /// Pass a clone to `FrameDecoder::with_config()` or a reference to
+    /// `encode_frame_with()` to decode or encode the frames of this
+    /// connection without a serial device.
+    pub fn frame_config(&self) -> &FrameConfig {
+        &self.config
     }
 
-    fn _start_send_state() -> SendState {
-        SendState::NotSending
+    /// Set the sentinel which starts each frame. Defaults to `SENTINEL`.
+    ///
+    /// This is the only sentinel sent, and also the only one accepted on
+    /// receive unless `set_accept_sentinels()` was called. Returns an
+    /// `Error` of kind `ErrorKind::InvalidFrame` if `sentinel` is `ESC` while
+    /// escaping is enabled.
+    pub fn set_sentinel(&mut self, sentinel: u8) -> Result<()> {
+        self.config.set_sentinel(sentinel)
     }
 
-    /// Schedule a frame to be sent. Returns `Err(Error)` if the frame is too long,
-    /// otherwise returns immediately with `Ok(())`.
-    pub fn schedule_send(&mut self, frame: Vec<u8>) -> Result<()> {
-        match self.send_state {
-            SendState::NotSending => {}
-            SendState::Sending(_) => {
-                return Err(Error::new("Previous send in progress. Hint: block_until_send_done().".into()));
-            }
-        }
+    /// Keep the receive buffer across frames. Defaults to `false`.
+    ///
+    /// By default each received frame takes the receive buffer with it, so
+    /// the next frame starts with an empty one. With `true`, frames are
+    /// copied out and the buffer keeps its capacity, which avoids growing a
+    /// new buffer for every frame at the cost of a copy.
+    pub fn set_retain_recv_buffer(&mut self, retain: bool) {
+        self.retain_recv_buffer = retain;
+    }
 
-        if frame.len() > u16::max_value() as usize {
-            return Err(Error::new("frame data too long".into()));
-        }
-        let mut buf = [0; 2];
-        byteorder::LittleEndian::write_u16(&mut buf, frame.len() as u16);
-        self.send_state = SendState::Sending( {
-            SendingState{
-                what_next: WhatNext::Sentinel,
-                index: 0,
-                header_bytes: buf,
-                frame: frame,
-            }});
-        Ok(())
+    /// The capacity of the receive buffer, in bytes.
+    pub fn recv_capacity(&self) -> usize {
+        self.receiver.capacity()
     }
 
-    /// Wait until previous send is done.
-    pub fn block_until_send_done(&mut self) -> Result<()> {
-        loop {
-            match self.send_state {
-                SendState::NotSending => {break;}
+    /// Release excess capacity of the receive buffers.
+    ///
+    /// Useful after an occasional large frame. A partially received frame
+    /// is kept.
+    pub fn shrink_recv_buffer(&mut self) {
+        self.receiver.shrink_to_fit();
+    }
+
+    /// Require `magic` after the sentinel of each frame. Defaults to none.
+    ///
+    /// A frame only starts once the sentinel and the whole magic sequence
+    /// were received, and on a mismatch the receiver waits for the next
+    /// sentinel, so a data byte which happens to equal the sentinel is
+    /// rarely taken for a frame start. The magic is covered by the checksum
+    /// and escaped like the header. It is only sent with
+    /// `FramingMode::LengthPrefixed`, and both ends must use the same magic.
+    /// Returns an `Error` of kind `ErrorKind::InvalidFrame` if `magic` is
+    /// longer than `MAX_MAGIC_LEN` bytes.
+    pub fn set_magic(&mut self, magic: &[u8]) -> Result<()> {
+        self.config.set_magic(magic)
+    }
+
+    /// Accept any of the given sentinels as a frame start on receive.
+    ///
+    /// This is useful while migrating peers from one sentinel to another.
+    /// Sending always uses the single sentinel from `set_sentinel()`. An
+    /// empty slice restores the default of accepting only that sentinel.
+    /// Returns an `Error` of kind `ErrorKind::InvalidFrame` if `sentinels`
+    /// contains `ESC` while escaping is enabled.
+    pub fn set_accept_sentinels(&mut self, sentinels: &[u8]) -> Result<()> {
+        self.config.set_accept_sentinels(sentinels)
+    }
+
+    /// Set a byte the peer sends as idle fill between frames.
+    ///
+    /// While waiting for a sentinel, this byte is consumed silently rather
+    /// than being counted in `discarded_bytes()`. Defaults to `None`.
+    pub fn set_idle_byte(&mut self, idle_byte: Option<u8>) {
+        self.receiver.idle_byte = idle_byte;
+    }
+
+    /// Treat runs of the sentinel as idle fill before a frame.
+    ///
+    /// For lines which idle at the sentinel value, consecutive sentinels are
+    /// collapsed and the frame starts at the first byte which is not a
+    /// sentinel. The byte following the sentinel can then never equal the
+    /// sentinel, so `schedule_send()` rejects frames for which it would: with
+    /// a length header, lengths whose low byte is the sentinel, and with
+    /// `FramingMode::FixedSize`, data starting with the sentinel. Chunks sent
+    /// with `set_auto_chunk()` are not checked. Defaults to `false`.
+    pub fn set_sentinel_is_idle(&mut self, sentinel_is_idle: bool) {
+        self.config.set_sentinel_is_idle(sentinel_is_idle);
+    }
+
+    /// The number of bytes discarded while waiting for a sentinel.
+    ///
+    /// A growing count indicates noise on the line or a loss of sync. Idle
+    /// fill set with `set_idle_byte()` is not counted.
+    pub fn discarded_bytes(&self) -> u64 {
+        self.receiver.discarded_bytes
+    }
+
+    /// Report noise on the line as an error.
+    ///
+    /// When more than `limit` bytes in a row are discarded while waiting
+    /// for a sentinel, `tick()` returns an `Error` of kind
+    /// `ErrorKind::RxNoise`, as after a baud rate mismatch. Receiving
+    /// continues on the next call, and another error follows after `limit`
+    /// more bytes. `None`, the default, discards any number of bytes
+    /// silently.
+    pub fn set_rx_noise_limit(&mut self, limit: Option<usize>) {
+        self.receiver.set_noise_limit(limit);
+    }
+
+    /// Enable or disable listen-only mode.
+    ///
+    /// In listen-only mode nothing is ever transmitted: `schedule_send()`
+    /// returns an error and the send half of `tick()` does nothing, while
+    /// frames are still received normally. Enabling it discards the send in
+    /// progress and all queued frames, so they will not be sent when the
+    /// mode is disabled.
+    pub fn set_listen_only(&mut self, listen_only: bool) {
+        if listen_only {
+            self.send_state = FramedConnection::<S>::_start_send_state();
+            self.send_queue.clear();
+        }
+        self.listen_only = listen_only;
+    }
+
+    /// Return whether listen-only mode is enabled.
+    pub fn is_listen_only(&self) -> bool {
+        self.listen_only
+    }
+
+    /// Set the payload of the end-of-transmission frame, or disable it.
+    ///
+    /// Both ends must agree on the marker. Received frames equal to it have
+    /// `FrameInfo::is_eot` set. Defaults to `None`, so no frame is taken for
+    /// the end of transmission.
+    pub fn set_eot_marker(&mut self, marker: Option<&[u8]>) {
+        self.eot_marker = marker.map(|m| m.to_vec());
+    }
+
+    /// Schedule an end-of-transmission frame to be sent.
+    ///
+    /// The receiver sees it with `FrameInfo::is_eot` set. Returns an `Error`
+    /// of kind `ErrorKind::InvalidFrame` if no marker was set with
+    /// `set_eot_marker()`.
+    pub fn schedule_eot(&mut self) -> Result<()> {
+        let marker = match self.eot_marker {
+            Some(ref marker) => marker.clone(),
+            None => return Err(Error::with_detail(ErrorKind::InvalidFrame, "no end-of-transmission marker".into())),
+        };
+        self.schedule_send(marker)
+    }
+
+    /// Stop reading from the serial device in `tick()`.
+    ///
+    /// A partially received frame is kept and receiving resumes where it
+    /// left off after `resume_recv()`. Meanwhile incoming bytes stay in the
+    /// serial device, which lets its flow control push back on the peer.
+    pub fn pause_recv(&mut self) {
+        self.recv_paused = true;
+    }
+
+    /// Resume reading from the serial device after `pause_recv()`.
+    pub fn resume_recv(&mut self) {
+        self.recv_paused = false;
+    }
+
+    /// Reset the receiver to wait for the next sentinel.
+    ///
+    /// The frame being received is discarded, even if it is complete but
+    /// was not yet taken with `get_frame()`. Frames skipped over by
+    /// `recv_frame_matching()` are kept.
+    pub fn abort_recv(&mut self) {
+        self.receiver.abort();
+    }
+
+    /// The index of the most recently sent frame, starting at 0.
+    ///
+    /// Returns `None` if no frame has been sent yet. Like `FrameInfo::index`
+    /// for received frames, this is local bookkeeping not sent on the wire.
+    pub fn last_tx_index(&self) -> Option<u64> {
+        self.n_frames_sent.checked_sub(1)
+    }
+
+    /// The number of BREAK conditions seen by `tick_with_line_status()`.
+    pub fn breaks_detected(&self) -> u64 {
+        self.breaks_detected
+    }
+
+    /// The number of collisions seen by `tick_with_line_status()`.
+    #[cfg(feature = "collision-detection")]
+    pub fn collisions(&self) -> u64 {
+        self.collisions
+    }
+
+    /// Configure the random backoff after a collision.
+    ///
+    /// After a collision, the frame is restarted after waiting between 1 and
+    /// `max_ticks` ticks. Nodes sharing a bus must use different `seed`
+    /// values, such as their bus address, or they will keep colliding.
+    #[cfg(feature = "collision-detection")]
+    pub fn set_collision_backoff(&mut self, max_ticks: u64, seed: u32) {
+        self.collision_backoff_max = core::cmp::max(1, max_ticks);
+        self.backoff_rng = if seed == 0 { BACKOFF_SEED } else { seed };
+    }
+
+    /// Abort the chunk being sent and schedule it to restart after a backoff.
+    ///
+    /// Chunks of the frame which were already sent are complete on the
+    /// wire, so only the current one is sent again.
+    #[cfg(feature = "collision-detection")]
+    fn _tx_collided(&mut self) -> Result<()> {
+        match self.send_state {
+            SendState::NotSending => return Ok(()),
+            SendState::Sending(ref mut s) => {
+                s.pending = None;
+                s.encoder.restart_chunk()?;
+                #[cfg(feature = "capture")]
+                self.tx_capture.truncate(s.capture_start);
+            },
+        }
+        self.collisions = self.collisions.saturating_add(1);
+        // xorshift32
+        let mut x = self.backoff_rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.backoff_rng = x;
+        let backoff = 1 + u64::from(x) % self.collision_backoff_max;
+        self.tx_backoff_until = self.ticks.saturating_add(backoff);
+        Ok(())
+    }
+
+    /// Drop a partially received frame if no byte arrives for `timeout`.
+    ///
+    /// The timeout is measured in the units of the `now` passed to
+    /// `tick_with_now()` and only applies while a frame is being received,
+    /// not while waiting for a sentinel. `tick()` does not check it.
+    /// Defaults to `None`.
+    pub fn set_recv_timeout(&mut self, timeout: Option<u64>) {
+        self.recv_timeout = timeout;
+    }
+
+    /// Set a receive size above which frames are counted as unusually large.
+    ///
+    /// Each frame growing beyond `limit` bytes increments `large_frames()`
+    /// once, as an early warning of a misbehaving peer. The frame is still
+    /// received normally. Defaults to `None`.
+    pub fn set_recv_soft_limit(&mut self, limit: Option<usize>) {
+        self.receiver.soft_limit = limit;
+    }
+
+    /// The number of frames received larger than the soft limit.
+    pub fn large_frames(&self) -> u64 {
+        self.receiver.large_frames
+    }
+
+    /// Apply a `ByteTransform` to all bytes sent and received, or remove it.
+    pub fn set_byte_transform(&mut self, transform: Option<Box<dyn ByteTransform + Send>>) {
+        self.transform = transform;
+    }
+
+    /// Set how frames are delimited. Defaults to `FramingMode::LengthPrefixed`.
+    ///
+    /// Both ends must use the same mode. Changing it while a frame is being
+    /// received may cause that frame to be misparsed.
+    pub fn set_framing_mode(&mut self, mode: FramingMode) {
+        self.config.set_framing_mode(mode);
+    }
+
+    /// Set the size of the length field of `FramingMode::LengthPrefixed`
+    /// frames. Defaults to `LengthWidth::U16`.
+    ///
+    /// `schedule_send()` rejects frames too long for the field with
+    /// `ErrorKind::FrameTooLong`. Both ends must use the same width.
+    pub fn set_length_width(&mut self, width: LengthWidth) {
+        self.config.set_length_width(width);
+    }
+
+    /// Send a tag byte between the sentinel and the length of every frame.
+    ///
+    /// This multiplexes several kinds of message over one link. Frames are
+    /// sent with `schedule_send_tagged()` and received with
+    /// `get_tagged_frame()`; `schedule_send()` sends tag 0. It has no effect
+    /// with `FramingMode::FixedSize`. Both ends must agree on this setting.
+    /// Defaults to off.
+    pub fn set_tagging(&mut self, enabled: bool) {
+        self.config.set_tagging(enabled);
+    }
+
+    /// Drop received frames with `tag` whose length is not within `min..=max`.
+    ///
+    /// With `Some((min, max))`, a frame whose header declares an implausible
+    /// length for its tag, most likely because of corruption, is dropped
+    /// before its data is received. It counts as a resync and in
+    /// `tag_length_errors()`. With chunking, the length of the reassembled
+    /// frame is checked. `None` removes the bounds of `tag`; frames with a
+    /// tag without bounds are not checked. Only applies with tagging.
+    pub fn set_tag_bounds(&mut self, tag: u8, bounds: Option<(usize, usize)>) {
+        self.receiver.set_tag_bounds(tag, bounds);
+    }
+
+    /// The number of frames dropped for a length outside the bounds of their tag.
+    ///
+    /// See `set_tag_bounds()`.
+    pub fn tag_length_errors(&self) -> u64 {
+        self.receiver.tag_length_errors
+    }
+
+    /// Set the byte order of the length field of `FramingMode::LengthPrefixed`
+    /// frames. Defaults to `Endianness::Little`.
+    ///
+    /// The checksum is always sent little endian. Both ends must use the same
+    /// byte order. This takes an `Endianness` or one of the `LittleEndian`,
+    /// `BigEndian` and `NativeEndian` markers.
+    pub fn set_endianness<E: Into<Endianness>>(&mut self, endianness: E) {
+        self.config.set_endianness(endianness);
+    }
+
+    /// Split frames into chunks of at most `max_chunk` bytes on the wire.
+    ///
+    /// With `Some(max_chunk)`, the header of every chunk carries a flags byte
+    /// after the length marking whether more chunks of the frame follow. The
+    /// receiver reassembles the chunks and delivers one frame, so frames
+    /// longer than the length field allows can be sent. `max_chunk` is
+    /// clamped to between 1 and 65535, and to the largest length the field
+    /// set with `set_length_width()` holds. Both ends must enable this, and it has
+    /// no effect with `FramingMode::FixedSize`. Defaults to `None`.
+    pub fn set_auto_chunk(&mut self, max_chunk: Option<usize>) {
+        self.config.set_auto_chunk(max_chunk);
+    }
+
+    /// Pass every frame sent and received to `tee`, or stop doing so.
+    ///
+    /// The tee is called with the payload when a frame finishes sending or
+    /// receiving. It sees frames in addition to, not instead of, normal
+    /// delivery by `get_frame()`.
+    pub fn set_frame_tee(&mut self, tee: Option<FrameTee>) {
+        self.frame_tee = tee;
+    }
+
+    /// Call `f` whenever sending finishes and there is nothing more to send.
+    ///
+    /// This is the moment to, for example, release an RS-485 driver.
+    pub fn set_on_send_idle(&mut self, f: Option<Box<dyn FnMut() + Send>>) {
+        self.on_send_idle = f;
+    }
+
+    /// Enable a CRC-16 (CCITT) checksum after each frame. Defaults to off.
+    ///
+    /// The checksum covers the header and data and is sent as 2 little
+    /// endian bytes. A received frame with a wrong checksum still completes,
+    /// but `get_frame()` returns an error instead of the corrupt data. Both
+    /// ends must agree on this setting.
+    pub fn set_checksum(&mut self, enabled: bool) {
+        self.config.set_checksum(enabled);
+    }
+
+    /// Escape sentinel bytes within frames. Defaults to off.
+    ///
+    /// Any sentinel or `ESC` byte after the sentinel, including in the
+    /// header and checksum, is sent as `ESC` followed by the byte XOR `ESC`,
+    /// so the sentinel only appears at the start of a frame. Both ends must
+    /// agree on this setting.
+    ///
+    /// A sentinel received in the middle of a frame then means bytes were
+    /// lost. The partial frame is dropped, counted in `resync_count()`, and
+    /// a new frame is received starting at that sentinel.
+    ///
+    /// Every escape would then look like a frame start if a sentinel were
+    /// `ESC`, so this returns an `Error` of kind `ErrorKind::InvalidFrame`
+    /// when enabling it with such a sentinel.
+    pub fn set_escaping(&mut self, enabled: bool) -> Result<()> {
+        self.config.set_escaping(enabled)
+    }
+
+    /// The number of frames received with a wrong checksum.
+    pub fn checksum_errors(&self) -> u64 {
+        self.receiver.checksum_errors
+    }
+
+    /// Set the order in which waiting frames are returned by `get_frame()`.
+    ///
+    /// Frames wait when `recv_frame_matching()` or `request()` skip over
+    /// them. This also applies to `get_frame_info()` and `for_each_frame()`.
+    pub fn set_queue_order(&mut self, order: QueueOrder) {
+        self.queue_order = order;
+    }
+
+    /// Report receive errors alongside frames from `drain_outcomes()`.
+    ///
+    /// When enabled, a frame with a wrong checksum, `ErrorKind::RxNoise`
+    /// and `ErrorKind::RxError` no longer stop `tick()` or `get_frame()`
+    /// with an `Error`. Each is kept as a `FrameOutcome::Error` until
+    /// `drain_outcomes()` returns it, or until `get_frame_info()` returns a
+    /// frame received after it, and receiving continues. The error counters
+    /// are kept as usual. Disabling this drops the kept errors. Defaults to
+    /// off.
+    pub fn set_in_band_errors(&mut self, enabled: bool) {
+        self.in_band_errors = enabled;
+        if !enabled {
+            self.rejects.clear();
+        }
+    }
+
+    fn _reject(&mut self, kind: ErrorKind, frame: Vec<u8>) {
+        trace!("framed-serial: receive error kept in band");
+        self.rejects.push_back((self.n_frames_received, kind, frame));
+    }
+
+    /// Set the number of ticks to wait after sending a frame before the next.
+    ///
+    /// No bytes are transmitted during the gap, giving a slow peer time to
+    /// process each frame. Defaults to 0, sending frames back to back.
+    pub fn set_tx_inter_frame_gap_ticks(&mut self, ticks: u64) {
+        self.tx_inter_frame_gap_ticks = ticks;
+    }
+
+    /// Set the number of ticks to wait after receiving a byte before sending.
+    ///
+    /// On a half-duplex line this gives the peer time to release the bus
+    /// before a response is transmitted. A frame already being sent is not
+    /// interrupted. Defaults to 0, sending as soon as a frame is scheduled.
+    pub fn set_turnaround_ticks(&mut self, ticks: u64) {
+        self.turnaround_ticks = ticks;
+    }
+
+    /// Warn after more than `limit` consecutive ticks without progress.
+    ///
+    /// A tick makes progress if it sends a byte or completes a received
+    /// frame. Once more than `limit` ticks in a row made none, `tick()` sets
+    /// `TickProgress::idle_warning` until progress resumes. This catches a
+    /// polling loop which should be producing data but is not. `None`, the
+    /// default, never warns.
+    pub fn set_idle_tick_warn_limit(&mut self, limit: Option<u64>) {
+        self.idle_tick_warn_limit = limit;
+        self.idle_ticks = 0;
+    }
+
+    /// Limit transmission to `bytes` per window of `window_ticks` ticks.
+    ///
+    /// Once the budget for the current window is used up, nothing more is
+    /// sent until the next window starts. This caps the long-run average
+    /// throughput, for example to share a link with other connections.
+    pub fn set_tx_rate_limit(&mut self, bytes: usize, window_ticks: u64) {
+        self.tx_rate_limit = Some((bytes, core::cmp::max(1, window_ticks)));
+        self.tx_window_start = self.ticks;
+        self.tx_window_bytes = 0;
+    }
+
+    /// Remove a limit set with `set_tx_rate_limit()`.
+    pub fn clear_tx_rate_limit(&mut self) {
+        self.tx_rate_limit = None;
+    }
+
+    /// Set the window over which `throughput()` is computed. Defaults to 5 seconds.
+    #[cfg(feature = "std")]
+    pub fn set_throughput_window(&mut self, window: Duration) {
+        self.throughput_window = window;
+    }
+
+    /// The bytes put on the wire for the most recently sent frame.
+    ///
+    /// This is the sentinel, header and data as written to the serial
+    /// device, after any byte transform. Returns `None` until a frame was
+    /// sent.
+    #[cfg(feature = "capture")]
+    pub fn last_tx_wire_bytes(&self) -> Option<&[u8]> {
+        self.last_tx_wire.as_deref()
+    }
+
+    /// The rate of frames sent and received over the recent window.
+    #[cfg(feature = "std")]
+    pub fn throughput(&self) -> Throughput {
+        let now = Instant::now();
+        let mut frames = 0;
+        let mut bytes: usize = 0;
+        for &(t, n_bytes) in self.throughput_events.iter().rev() {
+            if now.duration_since(t) > self.throughput_window {
+                break;
+            }
+            frames += 1;
+            bytes = bytes.saturating_add(n_bytes);
+        }
+        let secs = self.throughput_window.as_secs_f64();
+        if secs == 0.0 {
+            return Throughput { frames_per_sec: 0.0, bytes_per_sec: 0.0 };
+        }
+        Throughput {
+            frames_per_sec: frames as f64 / secs,
+            bytes_per_sec: bytes as f64 / secs,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn _record_throughput(&mut self, n_bytes: usize) {
+        let now = Instant::now();
+        while let Some(&(t, _)) = self.throughput_events.front() {
+            if now.duration_since(t) <= self.throughput_window {
+                break;
+            }
+            self.throughput_events.pop_front();
+        }
+        self.throughput_events.push_back((now, n_bytes));
+    }
+
+    fn _frame_sent(&mut self, frame: &[u8]) {
+        self.n_frames_sent = self.n_frames_sent.saturating_add(1);
+        if let Some(ref mut tee) = self.frame_tee {
+            tee(Direction::Tx, frame);
+        }
+        #[cfg(feature = "std")]
+        self._record_throughput(frame.len());
+    }
+
+    /// Called when the frame in `recv_buf` is complete.
+    fn _frame_received(&mut self) {
+        self.n_frames_received = self.n_frames_received.saturating_add(1);
+        self.last_rx_frame_tick = self.ticks;
+        let frame = self.receiver.frame().unwrap_or(&[]);
+        if let Some(ref mut tee) = self.frame_tee {
+            tee(Direction::Rx, frame);
+        }
+        #[cfg(feature = "std")]
+        {
+            let n_bytes = frame.len();
+            self._record_throughput(n_bytes);
+        }
+    }
+
+    /// The number of times the receiver lost sync with the sender.
+    ///
+    /// This counts partially received frames which were discarded, and
+    /// garbage skipped while looking for a sentinel after a good frame.
+    pub fn resync_count(&self) -> u64 {
+        self.receiver.resyncs
+    }
+
+    /// Traffic counters since construction or the last `reset_stats()`.
+    pub fn stats(&self) -> Stats {
+        let base = &self.stats_base;
+        Stats {
+            bytes_sent: self.bytes_sent.saturating_sub(base.bytes_sent),
+            bytes_received: self.bytes_received.saturating_sub(base.bytes_received),
+            frames_sent: self.n_frames_sent.saturating_sub(base.frames_sent),
+            frames_received: self.n_frames_received.saturating_sub(base.frames_received),
+            discarded_bytes: self.receiver.discarded_bytes.saturating_sub(base.discarded_bytes),
+        }
+    }
+
+    /// Restart the counters returned by `stats()` from zero.
+    ///
+    /// Other counters, such as `discarded_bytes()` and frame indices, are
+    /// not affected.
+    pub fn reset_stats(&mut self) {
+        self.stats_base = Stats {
+            bytes_sent: self.bytes_sent,
+            bytes_received: self.bytes_received,
+            frames_sent: self.n_frames_sent,
+            frames_received: self.n_frames_received,
+            discarded_bytes: self.receiver.discarded_bytes,
+        };
+    }
+
+    /// Set the thresholds used by `health()`.
+    pub fn set_health_thresholds(&mut self, thresholds: HealthThresholds) {
+        self.health_thresholds = thresholds;
+    }
+
+    /// Summarize the health of the link.
+    ///
+    /// Ticks are counted by calls to `tick()`, so the staleness thresholds
+    /// depend on how often it is called.
+    pub fn health(&self) -> LinkHealth {
+        let n_frames = core::cmp::max(1, self.n_frames_received) as f64;
+        let resync_rate = self.receiver.resyncs as f64 / n_frames;
+        let checksum_error_rate = self.receiver.checksum_errors as f64 / n_frames;
+        let ticks_since_last_frame = self.ticks.saturating_sub(self.last_rx_frame_tick);
+        let thresholds = &self.health_thresholds;
+        let status = if ticks_since_last_frame > thresholds.dead_ticks {
+            LinkStatus::Dead
+        } else if ticks_since_last_frame > thresholds.stale_ticks {
+            LinkStatus::Stale
+        } else if resync_rate > thresholds.degraded_resync_rate ||
+                checksum_error_rate > thresholds.degraded_checksum_error_rate {
+            LinkStatus::Degraded
+        } else {
+            LinkStatus::Healthy
+        };
+        let rx_queue_depth = self.available_frames();
+        let tx_queue_depth = self.pending_send_count();
+        LinkHealth {
+            status,
+            resync_rate,
+            checksum_error_rate,
+            ticks_since_last_frame,
+            rx_queue_depth,
+            tx_queue_depth,
+        }
+    }
+
+    /// Yield from the send half of `tick()` after every `n` bytes written.
+    ///
+    /// With `Some(n)`, `tick()` reports the send as not done once `n` bytes
+    /// have been written, even if the transmitter would accept more. This
+    /// lets a cooperative scheduler run other tasks while a large frame is
+    /// sent. At least one byte is written per tick. The default, `None`,
+    /// writes until the transmitter is full or the frame is complete.
+    pub fn set_tx_chunk_yield(&mut self, n: Option<usize>) {
+        self.tx_chunk_yield = n;
+    }
+
+    fn _start_send_state() -> SendState {
+        SendState::NotSending
+    }
+
+    /// Schedule a frame to be sent. Returns `Err(Error)` if the frame is too long,
+    /// otherwise returns immediately with `Ok(())`.
+    ///
+    /// If a frame is already being sent, this frame is queued and sent after
+    /// it. Frames are sent in the order scheduled.
+    pub fn schedule_send(&mut self, frame: Vec<u8>) -> Result<()> {
+        let tag = if self.config.tagging { Some(0) } else { None };
+        self._schedule_send(tag, frame)
+    }
+
+    /// Schedule a frame to be sent, unless one is already being sent.
+    ///
+    /// Unlike `schedule_send()`, which queues the frame, this returns an
+    /// `Error` of kind `ErrorKind::Busy` while a frame is being sent or
+    /// waits to be sent, leaving those untouched. Otherwise it fails as
+    /// `schedule_send()` does.
+    pub fn try_schedule_send(&mut self, frame: Vec<u8>) -> Result<()> {
+        if self.is_sending() {
+            return Err(Error::from_kind(ErrorKind::Busy));
+        }
+        self.schedule_send(frame)
+    }
+
+    /// Schedule a copy of `frame` to be sent.
+    ///
+    /// This is `schedule_send()` for data which the caller keeps. The copy
+    /// is made into the buffer of a frame sent earlier, so sending from the
+    /// same staging buffer repeatedly does not allocate once that buffer is
+    /// large enough.
+    pub fn schedule_send_slice(&mut self, frame: &[u8]) -> Result<()> {
+        let mut buf = core::mem::take(self.spare_send_buf.get_or_insert_with(Vec::new));
+        buf.clear();
+        buf.extend_from_slice(frame);
+        self.schedule_send(buf)
+    }
+
+    /// Schedule a frame to be sent with `tag` in its header.
+    ///
+    /// Returns an `Error` of kind `ErrorKind::InvalidFrame` unless tagging
+    /// is enabled with `set_tagging()`, and otherwise fails as
+    /// `schedule_send()` does.
+    pub fn schedule_send_tagged(&mut self, tag: u8, frame: Vec<u8>) -> Result<()> {
+        if !self.config.tagging || self.config.framing_mode != FramingMode::LengthPrefixed {
+            return Err(Error::with_detail(ErrorKind::InvalidFrame, "tagging is disabled".into()));
+        }
+        self._schedule_send(Some(tag), frame)
+    }
+
+    /// Schedule a frame to be sent with `crc` as its checksum.
+    ///
+    /// This is a testing aid for checking that the peer drops frames with a
+    /// wrong checksum; `crc` is sent in place of the computed checksum of
+    /// every chunk of the frame. Returns an `Error` of kind
+    /// `ErrorKind::InvalidFrame` unless checksums are enabled with
+    /// `set_checksum()`, and otherwise fails as `schedule_send()` does.
+    pub fn schedule_send_with_crc(&mut self, frame: Vec<u8>, crc: u16) -> Result<()> {
+        if !self.config.checksum {
+            return Err(Error::with_detail(ErrorKind::InvalidFrame, "checksums are disabled".into()));
+        }
+        let tag = if self.config.tagging { Some(0) } else { None };
+        self._schedule_send_with_crc(tag, frame, Some(crc))
+    }
+
+    fn _schedule_send(&mut self, tag: Option<u8>, frame: Vec<u8>) -> Result<()> {
+        self._schedule_send_with_crc(tag, frame, None)
+    }
+
+    fn _schedule_send_with_crc(&mut self, tag: Option<u8>, frame: Vec<u8>, crc: Option<u16>) -> Result<()> {
+        if self.listen_only {
+            return Err(Error::from_kind(ErrorKind::ListenOnly));
+        }
+        let encoder = FrameEncoder::with_crc(&self.config, tag, frame, crc)?;
+        match self.send_state {
+            SendState::NotSending => self.send_state = SendState::Sending(SendingState::new(encoder)),
+            SendState::Sending(_) => self.send_queue.push_back(encoder),
+        }
+        Ok(())
+    }
+
+    /// Stop sending the current frame and return it.
+    ///
+    /// Returns `None` if no frame is being sent. The next queued frame, if
+    /// any, is sent on the following `tick()`. The peer receives a truncated
+    /// frame. With escaping enabled, it drops that frame when the next one
+    /// starts; otherwise it may take the bytes of the next frame as the rest
+    /// of it.
+    pub fn abort_send(&mut self) -> Option<Vec<u8>> {
+        let next = match self.send_queue.pop_front() {
+            Some(next) => SendState::Sending(SendingState::new(next)),
+            None => SendState::NotSending,
+        };
+        #[cfg(feature = "capture")]
+        self.tx_capture.clear();
+        match core::mem::replace(&mut self.send_state, next) {
+            SendState::NotSending => None,
+            SendState::Sending(s) => Some(s.encoder.into_frame()),
+        }
+    }
+
+    /// The number of frames scheduled but not yet completely sent.
+    ///
+    /// This includes the frame being sent.
+    pub fn pending_send_count(&self) -> usize {
+        match self.send_state {
+            SendState::NotSending => self.send_queue.len(),
+            SendState::Sending(_) => self.send_queue.len().saturating_add(1),
+        }
+    }
+
+    /// Wait until previous send is done.
+    pub fn block_until_send_done(&mut self) -> Result<()> {
+        loop {
+            match self.send_state {
+                SendState::NotSending => {break;}
                 SendState::Sending(_) => {}
             }
             self.tick()?;
@@ -265,71 +1904,317 @@ impl<S> FramedConnection<S>
         Ok(())
     }
 
+    /// Like `block_until_send_done()`, but call `tick()` at most `max_ticks`
+    /// times.
+    ///
+    /// Returns an `Error` of kind `ErrorKind::WouldBlock` if frames remain
+    /// to be sent after that, for example because the serial device stopped
+    /// accepting bytes. They stay scheduled, so this can be called again.
+    /// With the `std` feature, the thread yields after each tick which sent
+    /// nothing.
+    pub fn flush_with_limit(&mut self, max_ticks: usize) -> Result<()> {
+        for _ in 0..max_ticks {
+            if let SendState::NotSending = self.send_state {
+                return Ok(());
+            }
+            #[cfg(feature = "std")]
+            let bytes_sent = self.bytes_sent;
+            if self.tick()?.send_is_done {
+                return Ok(());
+            }
+            #[cfg(feature = "std")]
+            {
+                if self.bytes_sent == bytes_sent {
+                    std::thread::yield_now();
+                }
+            }
+        }
+        match self.send_state {
+            SendState::NotSending => Ok(()),
+            SendState::Sending(_) => Err(Error::from_kind(ErrorKind::WouldBlock)),
+        }
+    }
+
     /// Service the connection.
     pub fn tick(&mut self) -> Result<TickProgress> {
+        self._tick(true)
+    }
+
+    /// Service the connection, given the current time of a monotonic clock.
+    ///
+    /// This is `tick()` which also enforces `set_recv_timeout()`. The
+    /// connection is serviced first. If that received no byte, and none
+    /// arrived in calls to `tick()` since the last call to this method, while
+    /// the last byte of a partially received frame arrived more than the
+    /// timeout before `now`, the frame is dropped, counted in
+    /// `resync_count()`, and an error of kind `ErrorKind::FrameTimeout` is
+    /// returned. The next call continues normally.
+    pub fn tick_with_now(&mut self, now: u64) -> Result<TickProgress> {
+        let progress = self.tick();
+        if self.bytes_received != self.rx_bytes_at_now {
+            // bytes arrived during this call or plain `tick()` calls before it
+            self.rx_bytes_at_now = self.bytes_received;
+            self.last_rx_now = now;
+        } else if let Some(timeout) = self.recv_timeout {
+            if progress.is_ok() && self.is_receiving() && now.saturating_sub(self.last_rx_now) > timeout {
+                self._resync_recv();
+                return Err(Error::from_kind(ErrorKind::FrameTimeout));
+            }
+        }
+        progress
+    }
+
+    fn _tick(&mut self, clear_to_send: bool) -> Result<TickProgress> {
+        self.ticks = self.ticks.saturating_add(1);
+        let bytes_sent = self.bytes_sent;
+        let bytes_received = self.bytes_received;
+        let frames_received = self.n_frames_received;
+        let send_is_done = if clear_to_send {
+            self._send_tick()?
+        } else {
+            match self.send_state {
+                SendState::NotSending => true,
+                SendState::Sending(_) => false,
+            }
+        };
+        let recv_is_done = self._recv_tick()?;
+        if self.bytes_sent != bytes_sent || self.n_frames_received != frames_received {
+            self.idle_ticks = 0;
+        } else {
+            self.idle_ticks = self.idle_ticks.saturating_add(1);
+        }
+        let idle_warning = match self.idle_tick_warn_limit {
+            Some(limit) => self.idle_ticks > limit,
+            None => false,
+        };
         Ok(TickProgress {
-            send_is_done: self._send_tick()?,
-            recv_is_done: self._recv_tick()?,
+            send_is_done,
+            recv_is_done,
+            idle_warning,
+            bytes_sent: self.bytes_sent.saturating_sub(bytes_sent) as usize,
+            bytes_received: self.bytes_received.saturating_sub(bytes_received) as usize,
         })
     }
 
+    /// Discard a partially received frame and wait for the next sentinel.
+    ///
+    /// A completed frame which was not yet retrieved is kept.
+    fn _resync_recv(&mut self) {
+        self.receiver.resync();
+    }
+
     /// return bool to describe whether send is done.
     fn _send_tick(&mut self) -> Result<bool> {
+        if self.listen_only {
+            return Ok(true);
+        }
+        if let SendState::NotSending = self.send_state {
+            return Ok(true);
+        }
+        let mut n_written: usize = 0;
+        loop {
+            if !self._send_frame_tick(&mut n_written)? {
+                return Ok(false);
+            }
+            // continue with the next frame without a gap on the wire
+            match self.send_queue.pop_front() {
+                Some(next) => {
+                    trace!("framed-serial: sending queued frame of {=usize} bytes", next.frame().len());
+                    self.send_state = SendState::Sending(SendingState::new(next));
+                },
+                None => break,
+            }
+        }
+        if let Some(ref mut f) = self.on_send_idle {
+            f();
+        }
+        Ok(true)
+    }
+
+    /// return bool to describe whether sending the current frame is done.
+    ///
+    /// `n_written` counts the bytes written during this tick.
+    fn _send_frame_tick(&mut self, n_written: &mut usize) -> Result<bool> {
+        #[cfg(feature = "collision-detection")]
+        let mut collided = false;
         match self.send_state {
             SendState::NotSending => {
                 return Ok(true);
             },
             SendState::Sending(ref mut s) => {
-                loop {
+                let chunk_starting = s.pending.is_none() && s.encoder.at_chunk_start();
+                #[cfg(feature = "collision-detection")]
+                {
+                    if chunk_starting && self.ticks <= self.tx_backoff_until {
+                        // backing off after a collision
+                        return Ok(false);
+                    }
+                }
+                if chunk_starting && s.encoder.is_first_chunk() {
+                    if let Some(done) = self.last_tx_done_tick {
+                        if self.tx_inter_frame_gap_ticks > 0 &&
+                                self.ticks.saturating_sub(done) <= self.tx_inter_frame_gap_ticks {
+                            // still in the gap after the previous frame
+                            return Ok(false);
+                        }
+                    }
+                    if let Some(rx) = self.last_rx_tick {
+                        if self.turnaround_ticks > 0 && self.ticks.saturating_sub(rx) <= self.turnaround_ticks {
+                            // the peer may still hold the bus
+                            return Ok(false);
+                        }
+                    }
+                }
+                if let Some((_, window_ticks)) = self.tx_rate_limit {
+                    if self.ticks.saturating_sub(self.tx_window_start) >= window_ticks {
+                        self.tx_window_start = self.ticks;
+                        self.tx_window_bytes = 0;
+                    }
+                }
+                while !s.encoder.is_done() {
                     // while we are not blocked on send, keep sending.
-                    let byte = match s.what_next {
-                        WhatNext::Sentinel => SENTINEL,
-                        WhatNext::Header => s.header_bytes[s.index],
-                        WhatNext::Data => s.frame[s.index],
+                    if let Some((budget, _)) = self.tx_rate_limit {
+                        if self.tx_window_bytes >= budget {
+                            // wait for the next window
+                            return Ok(false);
+                        }
+                    }
+                    let bulk = match self.bulk_write {
+                        Some(write) if s.pending.is_none() && self.transform.is_none() => Some(write),
+                        _ => None,
                     };
-                    match self.serial.putc_try(byte) {
-                        Ok(Some(_)) => {
-                            s.index += 1;
-                            let mut new_next: Option<WhatNext> = None;
-                            match s.what_next {
-                                WhatNext::Sentinel => {
-                                    new_next = Some(WhatNext::Header);
-                                    s.index = 0;
-                                },
-                                WhatNext::Header => {
-                                    if s.index == 2 {
-                                        new_next = Some(WhatNext::Data);
-                                        s.index = 0;
+                    if let Some(write) = bulk {
+                        let mut data = s.encoder.data(&self.config)?;
+                        if let Some((budget, _)) = self.tx_rate_limit {
+                            data = &data[..core::cmp::min(data.len(), budget.saturating_sub(self.tx_window_bytes))];
+                        }
+                        if let Some(chunk) = self.tx_chunk_yield {
+                            let left = core::cmp::max(1, chunk).saturating_sub(*n_written);
+                            data = &data[..core::cmp::min(data.len(), left)];
+                        }
+                        if !data.is_empty() {
+                            let (n, failed) = match write(&mut self.serial, data) {
+                                Ok(0) => return Ok(false),
+                                Ok(n) => (core::cmp::min(n, data.len()), false),
+                                Err(n) => (core::cmp::min(n, data.len()), true),
+                            };
+                            self.bytes_sent = self.bytes_sent.saturating_add(n as u64);
+                            #[cfg(feature = "capture")]
+                            self.tx_capture.extend_from_slice(&data[..n]);
+                            *n_written = n_written.saturating_add(n);
+                            self.tx_window_bytes = self.tx_window_bytes.saturating_add(n);
+                            if n > 0 {
+                                s.encoder.advance_data(n)?;
+                            }
+                            if failed {
+                                return Err(Error::from_kind(ErrorKind::TxError));
+                            }
+                            #[cfg(feature = "collision-detection")]
+                            {
+                                if let Some(check) = self.collision_check {
+                                    if check(&mut self.serial) {
+                                        collided = true;
+                                        break;
                                     }
+                                }
+                            }
+                            if let Some(chunk) = self.tx_chunk_yield {
+                                if *n_written >= chunk && !s.encoder.is_done() {
+                                    // yield to the caller before the next chunk
+                                    return Ok(false);
+                                }
+                            }
+                            continue;
+                        }
+                    }
+                    let byte = match s.pending.take() {
+                        Some(byte) => byte,
+                        None => {
+                            let byte = match s.encoder.peek(&self.config)? {
+                                Some(byte) => byte,
+                                None => {
+                                    return Err(Error::with_detail(ErrorKind::Internal, "send index out of range".into()));
                                 },
-                                WhatNext::Data => {
-                                    if s.index == s.frame.len() {
-                                        // don't send more
+                            };
+                            match self.transform {
+                                Some(ref mut t) => t.encode(byte),
+                                None => byte,
+                            }
+                        },
+                    };
+                    match self.serial.putc_try(byte) {
+                        Ok(Some(_)) => {
+                            self.bytes_sent = self.bytes_sent.saturating_add(1);
+                            #[cfg(all(feature = "capture", feature = "collision-detection"))]
+                            {
+                                if s.encoder.at_chunk_start() {
+                                    s.capture_start = self.tx_capture.len();
+                                }
+                            }
+                            #[cfg(feature = "capture")]
+                            self.tx_capture.push(byte);
+                            *n_written = n_written.saturating_add(1);
+                            self.tx_window_bytes = self.tx_window_bytes.saturating_add(1);
+                            #[cfg(feature = "collision-detection")]
+                            {
+                                if let Some(check) = self.collision_check {
+                                    if check(&mut self.serial) {
+                                        collided = true;
                                         break;
                                     }
-                                },
+                                }
                             }
-                            if let Some(nn) = new_next {
-                                s.what_next = nn;
+                            s.encoder.advance(&self.config)?;
+                            if let Some(chunk) = self.tx_chunk_yield {
+                                if *n_written >= chunk && !s.encoder.is_done() {
+                                    // yield to the caller before the next chunk
+                                    return Ok(false);
+                                }
                             }
                         },
                         Ok(None) => {
+                            s.pending = Some(byte);
                             return Ok(false);
                         },
                         Err(_) => {
-                            return Err(Error::new("unexpected error during putc_try()".into()));
+                            return Err(Error::from_kind(ErrorKind::TxError));
                         }
                     }
                 }
             }
         }
+        #[cfg(feature = "collision-detection")]
+        {
+            if collided {
+                self._tx_collided()?;
+                return Ok(false);
+            }
+        }
         // we have completed sending a frame
-        self.send_state = SendState::NotSending;
+        trace!("framed-serial: frame sent");
+        self.last_tx_done_tick = Some(self.ticks);
+        #[cfg(feature = "capture")]
+        {
+            self.last_tx_wire = Some(core::mem::take(&mut self.tx_capture));
+        }
+        let send_state = core::mem::replace(&mut self.send_state, SendState::NotSending);
+        if let SendState::Sending(s) = send_state {
+            let frame = s.encoder.into_frame();
+            self._frame_sent(&frame);
+            if let Some(ref mut spare) = self.spare_send_buf {
+                if frame.capacity() > spare.capacity() {
+                    *spare = frame;
+                }
+            }
+        }
         Ok(true)
     }
 
     /// return bool to describe whether recv is done.
     fn _recv_tick(&mut self) -> Result<bool> {
+        if self.recv_paused {
+            return Ok(self.frame_ready());
+        }
 
         loop {
             // While we get characters, keep looping.
@@ -340,33 +2225,37 @@ impl<S> FramedConnection<S>
 
             match self.serial.getc_try() {
                 Ok(Some(byte)) => {
-                    let mut new_state: Option<RecvState> = None;
-                    match self.recv_state {
-                        RecvState::Unknown => {
-                            if byte == SENTINEL {
-                                new_state = Some(RecvState::Header(HeaderState{bytes: [0, 0], index: 0}))
-                            }
-                        },
-                        RecvState::Header(ref mut hs) => {
-                            hs.bytes[hs.index] = byte;
-                            hs.index += 1;
-                            if hs.index == 2 {
-                                let ds = DataState {
-                                    length: byteorder::LittleEndian::read_u16(&hs.bytes) as usize,
-                                };
-                                new_state = Some(RecvState::Data(ds));
-                            }
-                        },
-                        RecvState::Data(ref mut ds) => {
-                            self.recv_buf.push(byte);
-                            if self.recv_buf.len() == ds.length {
-                                // this frame is complete, stop polling for new data
-                                return Ok(true);
-                            }
+                    self.last_rx_tick = Some(self.ticks);
+                    self.bytes_received = self.bytes_received.saturating_add(1);
+                    let byte = match self.transform {
+                        Some(ref mut t) => t.decode(byte),
+                        None => byte,
+                    };
+                    let complete = match self.receiver.push(&self.config, byte) {
+                        Ok(complete) => complete,
+                        Err(ref e) if self.in_band_errors && e.kind() == ErrorKind::RxNoise => {
+                            self._reject(ErrorKind::RxNoise, Vec::new());
+                            false
                         },
+                        Err(e) => return Err(e),
                     };
-                    if let Some(ns) = new_state {
-                        self.recv_state=ns;
+                    if complete {
+                        if self.receiver.checksum_failed() {
+                            if self.in_band_errors {
+                                let frame = self.receiver.take_corrupt();
+                                self._reject(ErrorKind::ChecksumMismatch, frame);
+                                continue;
+                            }
+                            // keep it for `get_frame()` to report, stop polling for new data
+                            return Ok(true);
+                        }
+                        self._frame_received();
+                        let info = self._take_frame()?;
+                        trace!("framed-serial: frame received, {=usize} bytes", info.data.len());
+                        if self.queue_order == QueueOrder::CoalesceLatest {
+                            self.recv_queue.clear();
+                        }
+                        self.recv_queue.push_back(info);
                     }
                 },
                 Ok(None) => {
@@ -374,40 +2263,670 @@ impl<S> FramedConnection<S>
                     break;
                 },
                 Err(_) => {
-                    return Err(Error::new("unexpected error during getc_try()".into()))
+                    if self.in_band_errors {
+                        self._reject(ErrorKind::RxError, Vec::new());
+                        break;
+                    }
+                    return Err(Error::from_kind(ErrorKind::RxError))
                 },
             };
 
         }
-        Ok(false)
+        Ok(self.frame_ready())
+    }
+
+    /// Return whether a frame is being sent or waits to be sent.
+    pub fn is_sending(&self) -> bool {
+        match self.send_state {
+            SendState::NotSending => false,
+            SendState::Sending(_) => true,
+        }
+    }
+
+    /// Return whether a frame has started arriving but is not yet complete.
+    pub fn is_receiving(&self) -> bool {
+        self.receiver.is_receiving()
+    }
+
+    /// Report the phase of receiving, for diagnostics.
+    pub fn recv_progress(&self) -> RecvProgress {
+        self.receiver.progress()
+    }
+
+    /// Report the progress of the frame being sent, for diagnostics.
+    ///
+    /// Returns `None` if no frame is being sent.
+    pub fn send_progress(&self) -> Option<SendProgress> {
+        match self.send_state {
+            SendState::NotSending => None,
+            SendState::Sending(ref s) => {
+                Some(SendProgress { sent: s.encoder.payload_sent(), total: s.encoder.frame().len() })
+            },
+        }
+    }
+
+    /// Return whether `get_frame()` has a frame to return.
+    ///
+    /// Unlike `tick()`, this does not touch the serial device.
+    pub fn frame_ready(&self) -> bool {
+        !self.recv_queue.is_empty() || self.is_frame_complete()
+    }
+
+    /// The number of received frames waiting for `get_frame()`.
+    ///
+    /// `tick()` keeps reading while frames arrive, so several may be
+    /// waiting. A frame with a wrong checksum is counted and stops further
+    /// reading until `get_frame()` reports it.
+    pub fn available_frames(&self) -> usize {
+        self.recv_queue.len() + if self.is_frame_complete() { 1 } else { 0 }
     }
 
     /// Check if frame is complete.
-    fn is_frame_complete(&mut self) -> bool {
-        match self.recv_state {
-            RecvState::Unknown | RecvState::Header(_) => false,
-            RecvState::Data(ref ds) => ds.length == self.recv_buf.len(),
+    fn is_frame_complete(&self) -> bool {
+        self.receiver.is_frame_complete()
+    }
+
+    /// The number of bytes sent on the wire for a payload of `payload_len` bytes.
+    ///
+    /// With escaping enabled, this is the length before escaping, so it is a
+    /// lower bound.
+    pub fn wire_len(&self, payload_len: usize) -> usize {
+        let config = &self.config;
+        let checksum_len = if config.checksum { 2 } else { 0 };
+        let width = config.length_width;
+        match config.framing_mode {
+            FramingMode::LengthPrefixed => {
+                // the sentinel, magic, tag and checksum of each chunk
+                let framing_len = 1 + config.magic_len + if config.tagging { 1 } else { 0 } + checksum_len;
+                match config.auto_chunk {
+                    Some(max_chunk) => {
+                        let max_chunk = core::cmp::min(max_chunk as u64, width.max()) as usize;
+                        let n_full = payload_len / max_chunk;
+                        let rest = payload_len % max_chunk;
+                        let full = n_full.saturating_mul(framing_len + width.encoded_len(max_chunk) + 1);
+                        let last = if rest > 0 || n_full == 0 {
+                            framing_len + width.encoded_len(rest) + 1
+                        } else {
+                            0
+                        };
+                        full.saturating_add(last).saturating_add(payload_len)
+                    },
+                    None => payload_len.saturating_add(framing_len + width.encoded_len(payload_len)),
+                }
+            },
+            FramingMode::FixedSize(_) => payload_len.saturating_add(1 + checksum_len),
+        }
+    }
+
+    /// The number of ticks needed to send a payload of `payload_len` bytes.
+    ///
+    /// This assumes the transmitter accepts `bytes_per_tick` bytes per tick,
+    /// further limited by `set_tx_chunk_yield()`. Returns `usize::MAX` if no
+    /// bytes can be sent per tick.
+    pub fn ticks_to_send(&self, payload_len: usize, bytes_per_tick: usize) -> usize {
+        let bytes_per_tick = match self.tx_chunk_yield {
+            Some(chunk) => core::cmp::min(bytes_per_tick, core::cmp::max(1, chunk)),
+            None => bytes_per_tick,
+        };
+        if bytes_per_tick == 0 {
+            return usize::MAX;
+        }
+        self.wire_len(payload_len).div_ceil(bytes_per_tick)
+    }
+
+    /// Estimate the heap memory currently held by this connection, in bytes.
+    ///
+    /// This sums the capacities of the internal buffers. It does not include
+    /// the size of the `FramedConnection` itself or of the serial device.
+    pub fn heap_bytes(&self) -> usize {
+        let send_bytes = match self.send_state {
+            SendState::NotSending => 0,
+            SendState::Sending(ref s) => s.encoder.capacity(),
+        };
+        let send_bytes = send_bytes + self.send_queue.capacity() * core::mem::size_of::<FrameEncoder>() +
+            self.send_queue.iter().map(|e| e.capacity()).sum::<usize>();
+        #[cfg(feature = "std")]
+        let send_bytes = send_bytes + self.throughput_events.capacity() *
+            core::mem::size_of::<(Instant, usize)>();
+        #[cfg(feature = "capture")]
+        let send_bytes = send_bytes + self.tx_capture.capacity() +
+            self.last_tx_wire.as_ref().map_or(0, |v| v.capacity());
+        let queue_bytes = self.recv_queue.capacity() * core::mem::size_of::<FrameInfo>() +
+            self.recv_queue.iter().map(|info| info.data.capacity()).sum::<usize>() +
+            self.rejects.capacity() * core::mem::size_of::<(u64, ErrorKind, Vec<u8>)>() +
+            self.rejects.iter().map(|r| r.2.capacity()).sum::<usize>();
+        self.receiver.heap_bytes() + send_bytes + queue_bytes + self.config.accept_sentinels.capacity() +
+            self.eot_marker.as_ref().map_or(0, |m| m.capacity()) +
+            self.spare_send_buf.as_ref().map_or(0, |v| v.capacity())
+    }
+
+    /// Borrow the frame `get_frame()` would return, without consuming it.
+    ///
+    /// Returns `None` if no frame is available, or if the completed frame
+    /// has a wrong checksum. This does not call `tick()` or allocate.
+    pub fn peek_frame(&self) -> Option<&[u8]> {
+        if self._next_is_queued() {
+            let queued = match self.queue_order {
+                QueueOrder::Fifo => self.recv_queue.front(),
+                QueueOrder::Lifo | QueueOrder::CoalesceLatest => self.recv_queue.back(),
+            };
+            return queued.map(|info| &info.data[..]);
         }
+        self.receiver.frame()
+    }
+
+    /// Iterate over received frames.
+    ///
+    /// Each call to `next()` calls `tick()` until a frame is complete and
+    /// returns it. The iterator ends when the serial device reports a
+    /// receive error, as when it is closed, and the frames already received
+    /// were returned; other errors are returned as items. As with
+    /// `recv_frame_matching()`, the device timeout keeps this from spinning
+    /// the CPU while waiting.
+    pub fn frames(&mut self) -> Frames<'_, S> {
+        Frames { conn: self, done: false }
     }
 
     /// Get completed frame.
     pub fn get_frame(&mut self) -> Result<Vec<u8>> {
-        let frame = match self.recv_state {
-            RecvState::Unknown | RecvState::Header(_) => {
-                return Err(Error::new("frame not available".into()));
+        Ok(self.get_frame_info()?.data)
+    }
+
+    /// Copy the frame `get_frame()` would return into `dst`.
+    ///
+    /// Returns the frame length. The receive buffer is kept for the next
+    /// frame, so this does not allocate. If `dst` is too small, an `Error` of
+    /// kind `ErrorKind::BufferTooSmall` is returned and the frame is kept.
+    pub fn recv_into(&mut self, dst: &mut [u8]) -> Result<usize> {
+        let n = match self.peek_frame() {
+            Some(frame) => {
+                match dst.get_mut(..frame.len()) {
+                    Some(dst) => dst.copy_from_slice(frame),
+                    None => return Err(Error::from_kind(ErrorKind::BufferTooSmall)),
+                }
+                frame.len()
+            },
+            None if !self._next_is_queued() && self.is_frame_complete() => {
+                // a wrong checksum, reported once as `get_frame()` does
+                if self.queue_order == QueueOrder::CoalesceLatest {
+                    self.recv_queue.clear();
+                }
+                self._discard_frame();
+                return Err(Error::from_kind(ErrorKind::ChecksumMismatch));
             },
-            RecvState::Data(ref ds) => {
-                if self.recv_buf.len() == ds.length {
-                    let mut frame = Vec::with_capacity(0);
-                    core::mem::swap(&mut self.recv_buf,&mut frame);
-                    frame
-                } else {
-                    return Err(Error::new("frame not available".into()));
+            None => return Err(Error::from_kind(ErrorKind::FrameNotAvailable)),
+        };
+        if self._next_is_queued() {
+            self._pop_queued();
+        } else {
+            if self.queue_order == QueueOrder::CoalesceLatest {
+                self.recv_queue.clear();
+            }
+            self._discard_frame();
+        }
+        Ok(n)
+    }
+
+    /// Get completed frame along with its tag.
+    ///
+    /// Frames received while tagging is disabled have tag 0.
+    pub fn get_tagged_frame(&mut self) -> Result<(u8, Vec<u8>)> {
+        let info = self.get_frame_info()?;
+        Ok((info.tag.unwrap_or(0), info.data))
+    }
+
+    /// Get completed frame along with information about how it was framed.
+    pub fn get_frame_info(&mut self) -> Result<FrameInfo> {
+        let info = if self._next_is_queued() {
+            self._pop_queued()
+        } else {
+            None
+        };
+        let info = match info {
+            Some(info) => info,
+            None => {
+                if self.queue_order == QueueOrder::CoalesceLatest {
+                    self.recv_queue.clear();
                 }
+                self._take_frame()?
             },
         };
-        self.recv_state = FramedConnection::<S>::_start_recv_state();
-        Ok(frame)
+        // errors before this frame are passed
+        self.rejects.retain(|r| r.0 > info.index);
+        Ok(info)
     }
 
+    /// Whether the next frame is a waiting one rather than the completed one.
+    ///
+    /// `get_frame_info()`, `peek_frame()` and `recv_into()` all follow this,
+    /// so they agree on which frame is next, including one with a wrong
+    /// checksum.
+    fn _next_is_queued(&self) -> bool {
+        if self.queue_order != QueueOrder::Fifo && self.is_frame_complete() {
+            // the completed frame is newer than any waiting frame
+            return false;
+        }
+        !self.recv_queue.is_empty()
+    }
+
+    /// Drop the completed frame, keeping the buffer for the next frame.
+    fn _discard_frame(&mut self) {
+        self.receiver.discard_frame();
+    }
+
+    /// Remove the next waiting frame according to the queue order.
+    fn _pop_queued(&mut self) -> Option<FrameInfo> {
+        match self.queue_order {
+            QueueOrder::Fifo => self.recv_queue.pop_front(),
+            QueueOrder::Lifo => self.recv_queue.pop_back(),
+            QueueOrder::CoalesceLatest => {
+                let info = self.recv_queue.pop_back();
+                self.recv_queue.clear();
+                info
+            },
+        }
+    }
+
+    /// Borrow the serial device.
+    pub fn get_ref(&self) -> &S {
+        &self.serial
+    }
+
+    /// Mutably borrow the serial device, for example to change its settings.
+    ///
+    /// Reading or writing the device directly while a frame is being sent or
+    /// received corrupts that frame.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.serial
+    }
+
+    /// Return the serial device, discarding the connection.
+    ///
+    /// Any frame being sent or received is dropped. Use `finalize()` to
+    /// recover it.
+    pub fn into_inner(self) -> S {
+        self.serial
+    }
+
+    /// Shut down the connection, returning the serial device and any undelivered data.
+    ///
+    /// Nothing is sent or received; call `block_until_send_done()` first to
+    /// finish sending.
+    pub fn finalize(mut self) -> (S, FinalState) {
+        let mut received: Vec<FrameInfo> = self.recv_queue.drain(..).collect();
+        let partial_recv = if self.is_frame_complete() {
+            if let Ok(info) = self._take_frame() {
+                received.push(info);
+            }
+            Vec::new()
+        } else {
+            self.receiver.take_partial()
+        };
+        let send_state = core::mem::replace(&mut self.send_state, SendState::NotSending);
+        let unsent = match send_state {
+            SendState::NotSending => None,
+            SendState::Sending(s) => {
+                let bytes_sent = s.encoder.payload_sent();
+                Some(PartialSend { frame: s.encoder.into_frame(), bytes_sent })
+            },
+        };
+        let queued = self.send_queue.drain(..).map(|e| e.into_frame()).collect();
+        (self.serial, FinalState { unsent, queued, received, partial_recv })
+    }
+
+    /// Take every frame available to `get_frame()`, in the order it would return them.
+    ///
+    /// Returns an empty `Vec` if no frame is ready. A partially received
+    /// frame is kept, and frames with a wrong checksum are dropped. This
+    /// does not call `tick()`.
+    pub fn drain_frames(&mut self) -> Vec<Vec<u8>> {
+        let mut frames = Vec::with_capacity(self.available_frames());
+        while self.frame_ready() {
+            if let Ok(frame) = self.get_frame() {
+                frames.push(frame);
+            }
+        }
+        frames
+    }
+
+    /// Take every frame available to `get_frame()` along with the receive
+    /// errors kept by `set_in_band_errors()`.
+    ///
+    /// The outcomes are in the order they were received, whatever the queue
+    /// order. Returns an empty `Vec` if there are none. A partially received
+    /// frame is kept. This does not call `tick()`.
+    pub fn drain_outcomes(&mut self) -> Vec<FrameOutcome> {
+        let rejects = core::mem::take(&mut self.rejects);
+        let mut outcomes = Vec::with_capacity(self.available_frames().saturating_add(rejects.len()));
+        for (n_received, kind, frame) in rejects {
+            outcomes.push((n_received, false, FrameOutcome::Error(kind, frame)));
+        }
+        while self.frame_ready() {
+            match self.get_frame_info() {
+                Ok(info) => outcomes.push((info.index, true, FrameOutcome::Good(info))),
+                Err(e) => outcomes.push((self.n_frames_received, false, FrameOutcome::Error(e.kind(), Vec::new()))),
+            }
+        }
+        // an error seen after `n` frames comes before the frame with index `n`
+        outcomes.sort_by_key(|&(position, is_frame, _)| (position, is_frame));
+        outcomes.into_iter().map(|(_, _, outcome)| outcome).collect()
+    }
+
+    /// Take every frame available to `get_frame()` by copying it to the end of `arena`.
+    ///
+    /// The returned iterator yields the frames as slices of `arena`, in the
+    /// order `get_frame()` would return them. Frames from several calls can
+    /// share one arena, which the caller then frees at once with
+    /// `arena.clear()`, keeping its allocation for the next burst instead of
+    /// dropping a `Vec` per frame. Otherwise this behaves as `drain_frames()`.
+    pub fn drain_frames_in<'a>(&mut self, arena: &'a mut Vec<u8>) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let mut ends = Vec::with_capacity(self.available_frames());
+        let mut start = arena.len();
+        self.for_each_frame(|frame| {
+            arena.extend_from_slice(frame);
+            ends.push(arena.len());
+        });
+        let arena: &'a Vec<u8> = arena;
+        ends.into_iter().map(move |end| {
+            let frame = arena.get(start..end).unwrap_or(&[]);
+            start = end;
+            frame
+        })
+    }
+
+    /// Call `f` with each frame available to `get_frame()`, consuming them.
+    ///
+    /// Frames are passed as borrowed slices, which are only valid during the
+    /// call, so no frame is copied. Returns the number of frames processed.
+    /// This does not call `tick()`.
+    pub fn for_each_frame<F>(&mut self, mut f: F) -> usize
+        where F: FnMut(&[u8]),
+    {
+        let mut n_frames = 0;
+        let newest_first = self.queue_order != QueueOrder::Fifo;
+        if newest_first && self.is_frame_complete() {
+            if self.queue_order == QueueOrder::CoalesceLatest {
+                self.recv_queue.clear();
+            }
+            if let Some(frame) = self.receiver.frame() {
+                f(frame);
+                n_frames += 1;
+            }
+            self._discard_frame();
+        }
+        while let Some(info) = self._pop_queued() {
+            f(&info.data);
+            n_frames += 1;
+        }
+        if self.is_frame_complete() {
+            if let Some(frame) = self.receiver.frame() {
+                f(frame);
+                n_frames += 1;
+            }
+            self._discard_frame();
+        }
+        n_frames
+    }
+
+    /// Send a frame, blocking until it and any queued frames are sent.
+    ///
+    /// This calls `tick()` in a loop, so it is meant for simple programs
+    /// rather than event loops which service other work between ticks.
+    /// Frames received meanwhile are kept for `get_frame()`.
+    #[cfg(feature = "std")]
+    pub fn send_frame(&mut self, frame: Vec<u8>) -> Result<()> {
+        self.schedule_send(frame)?;
+        self.block_until_send_done()
+    }
+
+    /// Receive a frame, blocking until one arrives.
+    ///
+    /// As with `send_frame()`, this calls `tick()` in a loop and is not
+    /// suited to event loops. It relies on the serial device timeout to
+    /// avoid spinning the CPU, as with `SerialWrap`, and waits for as long
+    /// as it takes.
+    #[cfg(feature = "std")]
+    pub fn recv_frame(&mut self) -> Result<Vec<u8>> {
+        while !self.tick()?.recv_is_done {}
+        self.get_frame()
+    }
+
+    /// Wait for a frame for which `pred` returns `true`.
+    ///
+    /// This calls `tick()` until such a frame arrives or `timeout` elapses,
+    /// in which case `Ok(None)` is returned. Frames which do not match are
+    /// kept and returned by later calls to `get_frame()` in the order
+    /// received. Frames with a wrong checksum are dropped and counted in
+    /// `checksum_errors()`. The loop relies on the serial device timeout to
+    /// avoid spinning the CPU, as with `SerialWrap`.
+    #[cfg(feature = "std")]
+    pub fn recv_frame_matching<F>(&mut self, pred: F, timeout: Duration) -> Result<Option<Vec<u8>>>
+        where F: Fn(&[u8]) -> bool,
+    {
+        self._recv_frame_matching(0, pred, timeout)
+    }
+
+    /// `recv_frame_matching()`, ignoring the first `skip` waiting frames.
+    #[cfg(feature = "std")]
+    fn _recv_frame_matching<F>(&mut self, skip: usize, pred: F, timeout: Duration) -> Result<Option<Vec<u8>>>
+        where F: Fn(&[u8]) -> bool,
+    {
+        if let Some(pos) = self.recv_queue.iter().skip(skip).position(|info| pred(&info.data)) {
+            return Ok(self.recv_queue.remove(skip + pos).map(|info| info.data));
+        }
+        let start = Instant::now();
+        loop {
+            let n_queued = self.recv_queue.len();
+            self.tick()?;
+            self._queue_completed_frame()?;
+            if let Some(pos) = self.recv_queue.iter().skip(n_queued).position(|info| pred(&info.data)) {
+                return Ok(self.recv_queue.remove(n_queued + pos).map(|info| info.data));
+            }
+            if start.elapsed() >= timeout {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Send a frame and wait for a response for which `match_response` returns `true`.
+    ///
+    /// This blocks until the frame is sent and a matching response arrives,
+    /// returning an `Error` if that does not happen within `timeout`. Frames
+    /// received meanwhile which do not match are kept for `get_frame()`, as
+    /// with `recv_frame_matching()`, and frames with a wrong checksum are
+    /// dropped. Frames received before the call are never taken as the
+    /// response.
+    #[cfg(feature = "std")]
+    pub fn request<F>(&mut self, frame: Vec<u8>, match_response: F, timeout: Duration) -> Result<Vec<u8>>
+        where F: Fn(&[u8]) -> bool,
+    {
+        let start = Instant::now();
+        self.schedule_send(frame)?;
+        let n_stale = self.recv_queue.len();
+        loop {
+            let tick_state = self.tick()?;
+            self._queue_completed_frame()?;
+            if tick_state.send_is_done {
+                break;
+            }
+            if start.elapsed() >= timeout {
+                return Err(Error::with_detail(ErrorKind::Timeout, "sending request".into()));
+            }
+        }
+        let remaining = timeout.checked_sub(start.elapsed()).unwrap_or_default();
+        match self._recv_frame_matching(n_stale, match_response, remaining)? {
+            Some(response) => Ok(response),
+            None => Err(Error::with_detail(ErrorKind::Timeout, "waiting for response".into())),
+        }
+    }
+
+    /// Move the completed frame, if any, to the waiting frames.
+    ///
+    /// A frame with a wrong checksum is dropped, as it was already counted
+    /// in `checksum_errors()`, so waiting for another frame can continue.
+    #[cfg(feature = "std")]
+    fn _queue_completed_frame(&mut self) -> Result<()> {
+        if self.is_frame_complete() {
+            match self._take_frame() {
+                Ok(info) => self.recv_queue.push_back(info),
+                Err(ref e) if e.kind() == ErrorKind::ChecksumMismatch => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn _take_frame(&mut self) -> Result<FrameInfo> {
+        let mut info = self.receiver.take_frame(self.retain_recv_buffer)?;
+        info.is_eot = self.eot_marker.as_ref().is_some_and(|m| info.data == *m);
+        // the completed frame is the most recently received
+        info.index = self.n_frames_received.saturating_sub(1);
+        Ok(info)
+    }
+
+}
+
+impl<S> FrameTransport for FramedConnection<S>
+    where S : NonBlockingRx + NonBlockingTx,
+{
+    fn schedule_frame(&mut self, frame: Vec<u8>) -> Result<()> {
+        self.schedule_send(frame)
+    }
+
+    fn try_get_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        if !self.frame_ready() {
+            return Ok(None);
+        }
+        self.get_frame().map(Some)
+    }
+
+    fn tick(&mut self) -> Result<TickProgress> {
+        FramedConnection::tick(self)
+    }
+}
+
+/// Each `write()` sends its whole buffer as one frame.
+///
+/// `write()` only schedules the frame; `flush()` calls `tick()` until all
+/// scheduled frames are sent. If a tick transmits nothing, because the
+/// serial device is not accepting bytes, `flush()` returns an error of kind
+/// `std::io::ErrorKind::WouldBlock` and can be called again later.
+#[cfg(feature = "std")]
+impl<S> std::io::Write for FramedConnection<S>
+    where S : NonBlockingRx + NonBlockingTx,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.schedule_send(buf.to_vec())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        loop {
+            let bytes_sent = self.bytes_sent;
+            if self.tick()?.send_is_done {
+                return Ok(());
+            }
+            if self.bytes_sent == bytes_sent {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+        }
+    }
+}
+
+/// An iterator over received frames, created by `FramedConnection::frames()`.
+pub struct Frames<'a, S: 'a>
+    where S : NonBlockingRx + NonBlockingTx,
+{
+    conn: &'a mut FramedConnection<S>,
+    done: bool,
+}
+
+impl<'a, S> Iterator for Frames<'a, S>
+    where S : NonBlockingRx + NonBlockingTx,
+{
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Result<Vec<u8>>> {
+        loop {
+            if self.conn.frame_ready() {
+                return Some(self.conn.get_frame());
+            }
+            if self.done {
+                return None;
+            }
+            match self.conn.tick() {
+                Ok(_) => {},
+                Err(ref e) if e.kind() == ErrorKind::RxError => self.done = true,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl<S> FramedConnection<S>
+    where S : NonBlockingRx + NonBlockingTxBulk,
+{
+    /// Send frame data with `NonBlockingTx::puts_try()`. Defaults to off.
+    ///
+    /// Runs of payload bytes are written in one call, up to the next byte
+    /// to escape and within the limits of `set_tx_rate_limit()` and
+    /// `set_tx_chunk_yield()`. The sentinel, header and checksum, and all
+    /// bytes while a `ByteTransform` is set, are still written one at a
+    /// time.
+    pub fn set_bulk_write(&mut self, enabled: bool) {
+        self.bulk_write = if enabled {
+            Some(|s: &mut S, data: &[u8]| s.puts_try(data).map_err(|(n, _)| n))
+        } else {
+            None
+        };
+    }
+}
+
+impl<R, T> FramedConnection<Split<R, T>>
+    where R : NonBlockingRx,
+          T : NonBlockingTx,
+{
+    /// Create a new `FramedConnection` from separate receive and transmit devices.
+    ///
+    /// Takes ownership of both devices.
+    pub fn from_split(rx: R, tx: T) -> FramedConnection<Split<R, T>> {
+        FramedConnection::new(Split::new(rx, tx))
+    }
+}
+
+impl<S> FramedConnection<S>
+    where S : NonBlockingRx + NonBlockingTx + LineStatus,
+{
+    /// Service the connection, also acting on line conditions.
+    ///
+    /// This is `tick()` for serial devices implementing `LineStatus`. A BREAK
+    /// is treated as a frame boundary: any partially received frame is
+    /// discarded and the receiver waits for the next sentinel. While the
+    /// device is not clear to send, nothing is transmitted.
+    ///
+    /// With the `collision-detection` feature, the device is asked for a
+    /// collision after every write. A collision aborts the frame being sent,
+    /// and the frame is sent again after a random backoff. With
+    /// `set_auto_chunk()`, only the chunk being sent is sent again. See
+    /// `set_collision_backoff()`.
+    pub fn tick_with_line_status(&mut self) -> Result<TickProgress> {
+        if self.serial.take_break() {
+            self.breaks_detected = self.breaks_detected.saturating_add(1);
+            self._resync_recv();
+        }
+        #[cfg(feature = "collision-detection")]
+        {
+            if self.serial.last_tx_collided() {
+                self._tx_collided()?;
+            }
+            self.collision_check = Some(|s: &mut S| s.last_tx_collided());
+        }
+        let clear_to_send = self.serial.is_clear_to_send();
+        let progress = self._tick(clear_to_send);
+        #[cfg(feature = "collision-detection")]
+        {
+            self.collision_check = None;
+        }
+        progress
+    }
 }
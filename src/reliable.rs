@@ -0,0 +1,203 @@
+use embedded_serial::{NonBlockingRx, NonBlockingTx};
+use super::{Error, ErrorKind, FramedConnection, Result, VecDeque};
+
+#[cfg(feature = "collections")]
+use collections::vec::Vec;
+
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+/// The first payload byte of a data frame.
+const DATA: u8 = 0;
+
+/// The first payload byte of an acknowledgement.
+const ACK: u8 = 1;
+
+/// The most frames which may wait for an acknowledgement.
+const MAX_UNACKED: usize = 64;
+
+/// How many received sequence numbers are remembered to drop duplicates.
+///
+/// Together with `MAX_UNACKED`, this must stay below the 256 sequence
+/// numbers, so a number is not reused while it is still remembered.
+const SEEN_LEN: usize = 128;
+
+struct Unacked {
+    seq: u8,
+    /// The frame as sent, including the kind and sequence number.
+    frame: Vec<u8>,
+    sent_at: u64,
+    #[cfg(feature = "std")]
+    sent_instant: Instant,
+}
+
+/// Acknowledged delivery with retransmission over a `FramedConnection`.
+///
+/// Each frame sent with `send_reliable()` carries a sequence number, and
+/// the receiver answers it with a short acknowledgement. Frames which are
+/// not acknowledged in time are sent again, and the receiver drops
+/// duplicates, so every frame is delivered once. Both ends must use a
+/// `ReliableConnection` and be started together, and the connection should
+/// have checksums enabled so corrupt frames are dropped rather than
+/// delivered.
+pub struct ReliableConnection<S>
+    where S: NonBlockingRx + NonBlockingTx,
+{
+    conn: FramedConnection<S>,
+    retransmit_ticks: u64,
+    #[cfg(feature = "std")]
+    retransmit_timeout: Option<Duration>,
+    ticks: u64,
+    next_seq: u8,
+    unacked: VecDeque<Unacked>,
+    seen: VecDeque<u8>,
+    retransmits: u64,
+}
+
+impl<S> ReliableConnection<S>
+    where S: NonBlockingRx + NonBlockingTx,
+{
+    /// Constructor. Takes ownership of the connection.
+    ///
+    /// Frames are sent again if not acknowledged within `retransmit_ticks`
+    /// calls to `poll()`.
+    pub fn new(conn: FramedConnection<S>, retransmit_ticks: u64) -> ReliableConnection<S> {
+        ReliableConnection {
+            conn,
+            retransmit_ticks,
+            #[cfg(feature = "std")]
+            retransmit_timeout: None,
+            ticks: 0,
+            next_seq: 0,
+            unacked: VecDeque::new(),
+            seen: VecDeque::new(),
+            retransmits: 0,
+        }
+    }
+
+    /// Borrow the connection.
+    pub fn get_ref(&self) -> &FramedConnection<S> {
+        &self.conn
+    }
+
+    /// Mutably borrow the connection.
+    ///
+    /// Sending or receiving frames on it directly confuses the peer.
+    pub fn get_mut(&mut self) -> &mut FramedConnection<S> {
+        &mut self.conn
+    }
+
+    /// Return the connection. Unacknowledged frames are dropped.
+    pub fn into_inner(self) -> FramedConnection<S> {
+        self.conn
+    }
+
+    /// The number of frames sent but not yet acknowledged.
+    pub fn unacked_count(&self) -> usize {
+        self.unacked.len()
+    }
+
+    /// The number of times a frame was sent again.
+    pub fn retransmits(&self) -> u64 {
+        self.retransmits
+    }
+
+    /// Send frames again if not acknowledged within `timeout`, or restore
+    /// the timeout in calls to `poll()` set by `new()` with `None`.
+    ///
+    /// Unlike the tick count, this does not depend on how often `poll()` is
+    /// called, although frames are still only sent again from `poll()`.
+    /// Defaults to `None`.
+    #[cfg(feature = "std")]
+    pub fn set_retransmit_timeout(&mut self, timeout: Option<Duration>) {
+        self.retransmit_timeout = timeout;
+    }
+
+    /// The time since the frame with sequence number `seq` was last sent.
+    ///
+    /// Returns `None` unless the frame is waiting for an acknowledgement.
+    #[cfg(feature = "std")]
+    pub fn elapsed_since_send(&self, seq: u8) -> Option<Duration> {
+        self.unacked.iter().find(|u| u.seq == seq).map(|u| u.sent_instant.elapsed())
+    }
+
+    /// Schedule a frame for acknowledged delivery. Returns its sequence number.
+    ///
+    /// Returns an `Error` of kind `ErrorKind::Busy` if too many frames are
+    /// waiting for an acknowledgement, or an `Error` if the connection
+    /// refuses the frame.
+    pub fn send_reliable(&mut self, frame: Vec<u8>) -> Result<u8> {
+        if self.unacked.len() >= MAX_UNACKED {
+            return Err(Error::with_detail(ErrorKind::Busy, "too many unacknowledged frames".into()));
+        }
+        let seq = self.next_seq;
+        let mut wire = Vec::with_capacity(frame.len().saturating_add(2));
+        wire.push(DATA);
+        wire.push(seq);
+        wire.extend_from_slice(&frame);
+        self.conn.schedule_send(wire.clone())?;
+        self.unacked.push_back(Unacked {
+            seq,
+            frame: wire,
+            sent_at: self.ticks,
+            #[cfg(feature = "std")]
+            sent_instant: Instant::now(),
+        });
+        self.next_seq = seq.wrapping_add(1);
+        Ok(seq)
+    }
+
+    /// Service the connection. Returns a newly delivered frame, if any.
+    ///
+    /// Received frames are acknowledged, acknowledgements are matched with
+    /// sent frames, and overdue frames are sent again once the connection
+    /// has nothing else to send. Frames with a wrong checksum and frames not
+    /// sent by a `ReliableConnection` are dropped.
+    pub fn poll(&mut self) -> Result<Option<Vec<u8>>> {
+        self.ticks = self.ticks.saturating_add(1);
+        let mut delivered = None;
+        if self.conn.tick()?.recv_is_done {
+            let mut frame = match self.conn.get_frame() {
+                Ok(frame) => frame,
+                // the sender will time out and send it again
+                Err(ref e) if e.kind() == ErrorKind::ChecksumMismatch => Vec::new(),
+                Err(e) => return Err(e),
+            };
+            match (frame.first().cloned(), frame.get(1).cloned()) {
+                (Some(ACK), Some(seq)) => self.unacked.retain(|u| u.seq != seq),
+                (Some(DATA), Some(seq)) => {
+                    self.conn.schedule_send([ACK, seq].to_vec())?;
+                    if !self.seen.contains(&seq) {
+                        if self.seen.len() >= SEEN_LEN {
+                            self.seen.pop_front();
+                        }
+                        self.seen.push_back(seq);
+                        frame.drain(..2);
+                        delivered = Some(frame);
+                    }
+                },
+                _ => {},
+            }
+        }
+        if !self.conn.is_sending() {
+            for u in self.unacked.iter_mut() {
+                let overdue = self.ticks.saturating_sub(u.sent_at) >= self.retransmit_ticks;
+                #[cfg(feature = "std")]
+                let overdue = match self.retransmit_timeout {
+                    Some(timeout) => u.sent_instant.elapsed() >= timeout,
+                    None => overdue,
+                };
+                if overdue {
+                    self.conn.schedule_send(u.frame.clone())?;
+                    u.sent_at = self.ticks;
+                    #[cfg(feature = "std")]
+                    {
+                        u.sent_instant = Instant::now();
+                    }
+                    self.retransmits = self.retransmits.saturating_add(1);
+                }
+            }
+        }
+        Ok(delivered)
+    }
+}
@@ -1,6 +1,6 @@
 use serial::SerialPort;
 use super::embedded_serial;
-use super::{std, Error};
+use super::{std, Error, ErrorKind, NonBlockingTxBulk};
 
 /// Implment the traits required for a `FramedConnection` based
 /// on a `serial::SerialPort`.
@@ -12,6 +12,11 @@ pub struct SerialWrap<T>
     where T: SerialPort,
 {
     inner: T,
+    read_buf: Vec<u8>,
+    /// The next byte of `read_buf` to return.
+    read_pos: usize,
+    /// The number of bytes in `read_buf` from the last read.
+    read_len: usize,
 }
 
 impl<T> SerialWrap<T>
@@ -19,8 +24,41 @@ impl<T> SerialWrap<T>
 {
     /// Constructor
     pub fn new(port: T) -> SerialWrap<T> {
-        // do not block
-        SerialWrap {inner: port}
+        SerialWrap::with_read_buffer(port, 1)
+    }
+
+    /// Constructor reading up to `n` bytes from the port at once.
+    ///
+    /// `getc_try()` returns buffered bytes one at a time and only reads the
+    /// port again once they are used up, so receiving a large frame takes
+    /// fewer system calls. `n` is at least 1.
+    pub fn with_read_buffer(port: T, n: usize) -> SerialWrap<T> {
+        SerialWrap {
+            inner: port,
+            read_buf: vec![0; std::cmp::max(1, n)],
+            read_pos: 0,
+            read_len: 0,
+        }
+    }
+
+    /// Borrow the wrapped serial port.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Mutably borrow the wrapped serial port, for example to call `set_timeout()`.
+    ///
+    /// Reading or writing the port directly while a frame is being sent or
+    /// received corrupts that frame, as does reading while bytes are
+    /// buffered by `with_read_buffer()`.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Return the wrapped serial port. Buffered bytes not yet returned by
+    /// `getc_try()` are lost.
+    pub fn into_inner(self) -> T {
+        self.inner
     }
 }
 
@@ -29,15 +67,23 @@ impl<T> embedded_serial::NonBlockingRx for SerialWrap<T>
 {
     type Error=Error;
     fn getc_try(&mut self) -> Result<Option<u8>, Self::Error> {
-        let mut buf: [u8;1] = [0];
+        if self.read_pos < self.read_len {
+            let byte = self.read_buf[self.read_pos];
+            self.read_pos += 1;
+            return Ok(Some(byte));
+        }
 
-        match self.inner.read(&mut buf) {
-            Ok(1) => Ok(Some(buf[0])),
-            Ok(n_bytes) => return Err(Error::new(format!("no error, but {} bytes read.", n_bytes))),
+        match self.inner.read(&mut self.read_buf) {
+            Ok(0) => Err(Error::with_detail(ErrorKind::RxError, "no error, but 0 bytes read.".into())),
+            Ok(n_bytes) => {
+                self.read_len = std::cmp::min(n_bytes, self.read_buf.len());
+                self.read_pos = 1;
+                Ok(Some(self.read_buf[0]))
+            },
             Err(e) => {
                 match e.kind() {
                     std::io::ErrorKind::TimedOut => {Ok(None)},
-                    _ => return Err(Error::new(format!("Can't read, err {:?}", e))),
+                    _ => Err(Error::with_detail(ErrorKind::RxError, format!("Can't read, err {:?}", e))),
                 }
             },
         }
@@ -60,17 +106,37 @@ impl<T> embedded_serial::NonBlockingTx for SerialWrap<T>
         let buf: [u8; 1] = [ch];
         match self.inner.write(&buf) {
             Ok(0) => {
-                return Ok(None);
+                Ok(None)
             },
             Ok(1) => {
-                return Ok(Some(ch));
+                Ok(Some(ch))
             },
-            Ok(_) => {
-                unreachable!();
+            Ok(n_bytes) => {
+                Err(Error::with_detail(ErrorKind::TxError, format!("wrote 1 byte, but {} bytes reported.", n_bytes)))
             },
             Err(e) => {
-                return Err(Error::new(format!("write error {:?}",e)));
+                Err(Error::with_detail(ErrorKind::TxError, format!("write error {:?}",e)))
+            },
+        }
+    }
+
+    /// Write as much of `data` as the port accepts in one `write()` call.
+    fn puts_try<I>(&mut self, data: &I) -> Result<usize, (usize, Self::Error)>
+        where I: AsRef<[u8]> + ?Sized
+    {
+        let data = data.as_ref();
+        match self.inner.write(data) {
+            Ok(n_bytes) if n_bytes > data.len() => {
+                Err((0, Error::with_detail(ErrorKind::TxError,
+                    format!("wrote {} bytes, but {} bytes reported.", data.len(), n_bytes))))
             },
+            Ok(n_bytes) => Ok(n_bytes),
+            Err(e) => Err((0, Error::with_detail(ErrorKind::TxError, format!("write error {:?}", e)))),
         }
     }
 }
+
+impl<T> NonBlockingTxBulk for SerialWrap<T>
+    where T: SerialPort,
+{
+}
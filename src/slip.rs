@@ -0,0 +1,171 @@
+use embedded_serial::{NonBlockingRx, NonBlockingTx};
+use super::{Error, ErrorKind, FrameTransport, Result, TickProgress, VecDeque};
+
+#[cfg(feature = "collections")]
+use collections::vec::Vec;
+
+/// Ends a SLIP frame.
+const END: u8 = 0xC0;
+
+/// Starts an escape sequence.
+const ESC: u8 = 0xDB;
+
+/// Follows `ESC` for an `END` data byte.
+const ESC_END: u8 = 0xDC;
+
+/// Follows `ESC` for an `ESC` data byte.
+const ESC_ESC: u8 = 0xDD;
+
+/// Frames sent and received with SLIP (RFC 1055) over a serial device.
+///
+/// This talks to devices which frame with `END` (0xC0) bytes instead of a
+/// sentinel and length. Each frame is sent between two `END` bytes, with
+/// `END` and `ESC` (0xDB) in the data escaped, and empty frames between
+/// consecutive `END` bytes are ignored on receive. There are no
+/// checksums. The options of `FramedConnection` do not apply; use
+/// `FrameTransport` to write code for either.
+pub struct SlipConnection<S>
+    where S: NonBlockingRx + NonBlockingTx,
+{
+    serial: S,
+    /// Encoded bytes of the frames being sent.
+    send_buf: Vec<u8>,
+    /// The next byte of `send_buf` to write.
+    send_pos: usize,
+    recv_buf: Vec<u8>,
+    recv_escaped: bool,
+    recv_queue: VecDeque<Vec<u8>>,
+}
+
+impl<S> SlipConnection<S>
+    where S: NonBlockingRx + NonBlockingTx,
+{
+    /// Constructor. Takes ownership of the serial device.
+    pub fn new(serial: S) -> SlipConnection<S> {
+        SlipConnection {
+            serial,
+            send_buf: Vec::new(),
+            send_pos: 0,
+            recv_buf: Vec::new(),
+            recv_escaped: false,
+            recv_queue: VecDeque::new(),
+        }
+    }
+
+    /// Borrow the serial device.
+    pub fn get_ref(&self) -> &S {
+        &self.serial
+    }
+
+    /// Mutably borrow the serial device.
+    ///
+    /// Reading or writing the device directly while a frame is being sent or
+    /// received corrupts that frame.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.serial
+    }
+
+    /// Return the serial device. Frames not yet sent or retrieved are lost.
+    pub fn into_inner(self) -> S {
+        self.serial
+    }
+
+    /// Schedule a frame to be sent. Frames are sent in the order scheduled.
+    pub fn schedule_send(&mut self, frame: &[u8]) {
+        // reclaim the bytes already sent
+        let sent = core::cmp::min(self.send_pos, self.send_buf.len());
+        self.send_buf.drain(..sent);
+        self.send_pos = 0;
+        self.send_buf.push(END);
+        for &byte in frame {
+            match byte {
+                END => self.send_buf.extend_from_slice(&[ESC, ESC_END]),
+                ESC => self.send_buf.extend_from_slice(&[ESC, ESC_ESC]),
+                _ => self.send_buf.push(byte),
+            }
+        }
+        self.send_buf.push(END);
+    }
+
+    /// Return whether a frame is being sent.
+    pub fn is_sending(&self) -> bool {
+        self.send_pos < self.send_buf.len()
+    }
+
+    /// Return whether `get_frame()` has a frame to return.
+    pub fn frame_ready(&self) -> bool {
+        !self.recv_queue.is_empty()
+    }
+
+    /// Get the oldest received frame.
+    pub fn get_frame(&mut self) -> Result<Vec<u8>> {
+        self.recv_queue.pop_front().ok_or_else(|| Error::from_kind(ErrorKind::FrameNotAvailable))
+    }
+
+    /// Service the connection.
+    pub fn tick(&mut self) -> Result<TickProgress> {
+        let mut bytes_sent = 0;
+        while let Some(&byte) = self.send_buf.get(self.send_pos) {
+            match self.serial.putc_try(byte) {
+                Ok(Some(_)) => {
+                    self.send_pos += 1;
+                    bytes_sent += 1;
+                },
+                Ok(None) => break,
+                Err(_) => return Err(Error::from_kind(ErrorKind::TxError)),
+            }
+        }
+        let mut bytes_received = 0;
+        loop {
+            let byte = match self.serial.getc_try() {
+                Ok(Some(byte)) => byte,
+                Ok(None) => break,
+                Err(_) => return Err(Error::from_kind(ErrorKind::RxError)),
+            };
+            bytes_received += 1;
+            if self.recv_escaped {
+                self.recv_escaped = false;
+                // RFC 1055 keeps the byte after a stray `ESC` as it is
+                self.recv_buf.push(match byte {
+                    ESC_END => END,
+                    ESC_ESC => ESC,
+                    _ => byte,
+                });
+                continue;
+            }
+            match byte {
+                END if !self.recv_buf.is_empty() => {
+                    let frame = core::mem::take(&mut self.recv_buf);
+                    self.recv_queue.push_back(frame);
+                },
+                END => {},
+                ESC => self.recv_escaped = true,
+                _ => self.recv_buf.push(byte),
+            }
+        }
+        Ok(TickProgress {
+            recv_is_done: self.frame_ready(),
+            send_is_done: !self.is_sending(),
+            idle_warning: false,
+            bytes_sent,
+            bytes_received,
+        })
+    }
+}
+
+impl<S> FrameTransport for SlipConnection<S>
+    where S: NonBlockingRx + NonBlockingTx,
+{
+    fn schedule_frame(&mut self, frame: Vec<u8>) -> Result<()> {
+        self.schedule_send(&frame);
+        Ok(())
+    }
+
+    fn try_get_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        Ok(self.recv_queue.pop_front())
+    }
+
+    fn tick(&mut self) -> Result<TickProgress> {
+        SlipConnection::tick(self)
+    }
+}
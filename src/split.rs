@@ -0,0 +1,57 @@
+use super::embedded_serial;
+use super::NonBlockingTxBulk;
+
+/// Combine separate receive and transmit devices into one serial device.
+///
+/// Some hardware has the receiver and transmitter in different peripherals,
+/// each with its own driver. `Split` implements both traits required for a
+/// `FramedConnection` by reading from `rx` and writing to `tx`. See
+/// `FramedConnection::from_split()`.
+pub struct Split<R, T> {
+    rx: R,
+    tx: T,
+}
+
+impl<R, T> Split<R, T>
+    where R: embedded_serial::NonBlockingRx,
+          T: embedded_serial::NonBlockingTx,
+{
+    /// Constructor
+    pub fn new(rx: R, tx: T) -> Split<R, T> {
+        Split { rx, tx }
+    }
+
+    /// Return the receive and transmit devices.
+    pub fn into_inner(self) -> (R, T) {
+        (self.rx, self.tx)
+    }
+}
+
+impl<R, T> embedded_serial::NonBlockingRx for Split<R, T>
+    where R: embedded_serial::NonBlockingRx,
+{
+    type Error=R::Error;
+    fn getc_try(&mut self) -> Result<Option<u8>, Self::Error> {
+        self.rx.getc_try()
+    }
+}
+
+impl<R, T> embedded_serial::NonBlockingTx for Split<R, T>
+    where T: embedded_serial::NonBlockingTx,
+{
+    type Error=T::Error;
+    fn putc_try(&mut self, ch: u8) -> Result<Option<u8>, Self::Error> {
+        self.tx.putc_try(ch)
+    }
+
+    fn puts_try<I>(&mut self, data: &I) -> Result<usize, (usize, Self::Error)>
+        where I: AsRef<[u8]> + ?Sized
+    {
+        self.tx.puts_try(data)
+    }
+}
+
+impl<R, T> NonBlockingTxBulk for Split<R, T>
+    where T: NonBlockingTxBulk,
+{
+}
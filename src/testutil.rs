@@ -0,0 +1,183 @@
+use super::embedded_serial;
+use super::{FramedConnection, NonBlockingTxBulk};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// An in-memory loopback transport with a bounded transmit FIFO.
+///
+/// Bytes written with `putc_try` enter a FIFO of fixed depth and `putc_try`
+/// returns `Ok(None)` while it is full. Every call to `putc_try` or
+/// `getc_try` moves up to `tx_drain_rate` bytes from the FIFO to the receive
+/// side, where `getc_try` returns them. This exercises the backpressure
+/// paths of `FramedConnection` in benchmarks and tests.
+pub struct BenchSerial {
+    tx_fifo: VecDeque<u8>,
+    tx_fifo_depth: usize,
+    tx_drain_rate: usize,
+    rx_pending: VecDeque<u8>,
+    rx_feed_rate: Option<usize>,
+    rx_fed: usize,
+    byte_time: Option<Duration>,
+    now: Duration,
+    /// When the byte at the front of `tx_fifo` finishes transmitting.
+    tx_done_at: Option<Duration>,
+}
+
+impl BenchSerial {
+    /// Constructor
+    pub fn new(tx_fifo_depth: usize, tx_drain_rate: usize) -> BenchSerial {
+        BenchSerial {
+            tx_fifo: VecDeque::with_capacity(tx_fifo_depth),
+            tx_fifo_depth,
+            tx_drain_rate,
+            rx_pending: VecDeque::new(),
+            rx_feed_rate: None,
+            rx_fed: 0,
+            byte_time: None,
+            now: Duration::from_secs(0),
+            tx_done_at: None,
+        }
+    }
+
+    /// Simulate a line running at `baud` bits per second.
+    ///
+    /// Each call to `putc_try` or `getc_try` advances a virtual clock by the
+    /// time to transmit one byte of 10 bits. Bytes leave the transmit FIFO
+    /// one byte time apart, instead of `tx_drain_rate` per call, and become
+    /// available to `getc_try` once their transmission is complete.
+    pub fn with_baud(mut self, baud: u32) -> BenchSerial {
+        let nanos = 10_000_000_000 / u64::from(std::cmp::max(1, baud));
+        self.byte_time = Some(Duration::from_nanos(nanos));
+        self
+    }
+
+    /// The virtual time elapsed since construction. Only advances with
+    /// `with_baud()`.
+    pub fn virtual_now(&self) -> Duration {
+        self.now
+    }
+
+    /// Limit how many bytes `getc_try` returns in a row.
+    ///
+    /// After `n` bytes, `getc_try` returns `Ok(None)` once, as if the
+    /// receive FIFO had run empty, so each `tick()` receives at most `n`
+    /// bytes. `None`, the default, returns bytes while any are available.
+    pub fn set_rx_feed_rate(&mut self, n: Option<usize>) {
+        self.rx_feed_rate = n;
+    }
+
+    /// Queue bytes to be returned by `getc_try`, as if sent by a peer.
+    pub fn feed_rx(&mut self, data: &[u8]) {
+        self.rx_pending.extend(data);
+    }
+
+    fn drain_tx(&mut self) {
+        if let Some(byte_time) = self.byte_time {
+            self.now += byte_time;
+            while let Some(done) = self.tx_done_at {
+                if done > self.now {
+                    break;
+                }
+                if let Some(byte) = self.tx_fifo.pop_front() {
+                    self.rx_pending.push_back(byte);
+                }
+                self.tx_done_at = if self.tx_fifo.is_empty() { None } else { Some(done + byte_time) };
+            }
+            return;
+        }
+        for _ in 0..self.tx_drain_rate {
+            match self.tx_fifo.pop_front() {
+                Some(byte) => self.rx_pending.push_back(byte),
+                None => break,
+            }
+        }
+    }
+}
+
+impl embedded_serial::NonBlockingRx for BenchSerial {
+    type Error=Infallible;
+    fn getc_try(&mut self) -> Result<Option<u8>, Self::Error> {
+        self.drain_tx();
+        if let Some(n) = self.rx_feed_rate {
+            if self.rx_fed >= n {
+                self.rx_fed = 0;
+                return Ok(None);
+            }
+        }
+        match self.rx_pending.pop_front() {
+            Some(byte) => {
+                self.rx_fed += 1;
+                Ok(Some(byte))
+            },
+            None => {
+                self.rx_fed = 0;
+                Ok(None)
+            },
+        }
+    }
+}
+
+impl embedded_serial::NonBlockingTx for BenchSerial {
+    type Error=Infallible;
+    fn putc_try(&mut self, ch: u8) -> Result<Option<u8>, Self::Error> {
+        self.drain_tx();
+        if self.tx_fifo.len() >= self.tx_fifo_depth {
+            return Ok(None);
+        }
+        self.tx_fifo.push_back(ch);
+        if let (None, Some(byte_time)) = (self.tx_done_at, self.byte_time) {
+            self.tx_done_at = Some(self.now + byte_time);
+        }
+        Ok(Some(ch))
+    }
+}
+
+/// The default `puts_try()` is used, to exercise the bulk write path.
+impl NonBlockingTxBulk for BenchSerial {}
+
+/// One end of an in-memory serial line, made by `loopback()`.
+///
+/// Bytes written with `putc_try` are returned by the other end's
+/// `getc_try`, in order and without loss. Both directions are independent.
+pub struct LoopbackSerial {
+    rx: Arc<Mutex<VecDeque<u8>>>,
+    tx: Arc<Mutex<VecDeque<u8>>>,
+}
+
+impl LoopbackSerial {
+    /// The number of bytes written by the other end and not yet read.
+    pub fn rx_pending(&self) -> usize {
+        self.rx.lock().unwrap().len()
+    }
+}
+
+impl embedded_serial::NonBlockingRx for LoopbackSerial {
+    type Error=Infallible;
+    fn getc_try(&mut self) -> Result<Option<u8>, Self::Error> {
+        Ok(self.rx.lock().unwrap().pop_front())
+    }
+}
+
+impl embedded_serial::NonBlockingTx for LoopbackSerial {
+    type Error=Infallible;
+    fn putc_try(&mut self, ch: u8) -> Result<Option<u8>, Self::Error> {
+        self.tx.lock().unwrap().push_back(ch);
+        Ok(Some(ch))
+    }
+}
+
+impl NonBlockingTxBulk for LoopbackSerial {}
+
+/// Two `FramedConnection`s joined by an in-memory serial line.
+///
+/// Frames sent on either connection are received by the other. The ends
+/// may be moved to different threads.
+pub fn loopback() -> (FramedConnection<LoopbackSerial>, FramedConnection<LoopbackSerial>) {
+    let a_to_b = Arc::new(Mutex::new(VecDeque::new()));
+    let b_to_a = Arc::new(Mutex::new(VecDeque::new()));
+    let a = LoopbackSerial { rx: b_to_a.clone(), tx: a_to_b.clone() };
+    let b = LoopbackSerial { rx: a_to_b, tx: b_to_a };
+    (FramedConnection::new(a), FramedConnection::new(b))
+}
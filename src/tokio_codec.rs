@@ -0,0 +1,69 @@
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+use super::{encode_frame_with, FrameConfig, FrameDecoder};
+
+/// A `tokio_util` codec for the frames of a `FramedConnection`.
+///
+/// This allows frames to be read and written over any `AsyncRead` or
+/// `AsyncWrite` with `tokio_util::codec::Framed`. Frames are encoded and
+/// decoded by the same code as in a `FramedConnection`, with the options of
+/// a `FrameConfig`. Bytes before a sentinel are skipped, as with a
+/// `FramedConnection`.
+pub struct FramedCodec {
+    decoder: FrameDecoder,
+}
+
+impl FramedCodec {
+    /// Constructor, for frames of a `FramedConnection` with default settings.
+    pub fn new() -> FramedCodec {
+        FramedCodec::with_config(FrameConfig::new())
+    }
+
+    /// Create a `FramedCodec` for frames with the options `config`.
+    ///
+    /// See `FramedConnection::frame_config()`.
+    pub fn with_config(config: FrameConfig) -> FramedCodec {
+        FramedCodec { decoder: FrameDecoder::with_config(config) }
+    }
+
+    /// The options frames are encoded and decoded with.
+    pub fn config(&self) -> &FrameConfig {
+        self.decoder.config()
+    }
+}
+
+impl Default for FramedCodec {
+    fn default() -> FramedCodec {
+        FramedCodec::new()
+    }
+}
+
+impl Encoder<Vec<u8>> for FramedCodec {
+    type Error = std::io::Error;
+
+    /// Returns an `InvalidInput` error if the options cannot send `item`,
+    /// as when it is too long for a frame.
+    fn encode(&mut self, item: Vec<u8>, dst: &mut BytesMut) -> std::io::Result<()> {
+        dst.extend_from_slice(&encode_frame_with(self.decoder.config(), &item)?);
+        Ok(())
+    }
+}
+
+impl Decoder for FramedCodec {
+    type Item = Vec<u8>;
+    type Error = std::io::Error;
+
+    /// Bytes of an incomplete frame are consumed and kept until the rest
+    /// arrives. Bytes after a completed frame are left in `src`. Errors of
+    /// `FrameDecoder::push()` are returned as `std::io::Error`, after which
+    /// decoding can continue with the next frame.
+    fn decode(&mut self, src: &mut BytesMut) -> std::io::Result<Option<Vec<u8>>> {
+        while src.has_remaining() {
+            let byte = src.get_u8();
+            if let Some(frame) = self.decoder.push(byte)? {
+                return Ok(Some(frame));
+            }
+        }
+        Ok(None)
+    }
+}
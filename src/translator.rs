@@ -0,0 +1,90 @@
+use embedded_serial::{NonBlockingRx, NonBlockingTx};
+use super::{ErrorKind, FramedConnection, Result};
+
+#[cfg(feature = "collections")]
+use collections::vec::Vec;
+
+/// Forward frames between two connections which may be configured differently.
+///
+/// Each frame received on one connection is sent on the other, so links
+/// with, for example, different sentinels can be bridged. A received frame
+/// waits until the other connection has finished sending its previous frame.
+pub struct Translator<A, B>
+    where A: NonBlockingRx + NonBlockingTx,
+          B: NonBlockingRx + NonBlockingTx,
+{
+    a: FramedConnection<A>,
+    b: FramedConnection<B>,
+    dropped: u64,
+}
+
+impl<A, B> Translator<A, B>
+    where A: NonBlockingRx + NonBlockingTx,
+          B: NonBlockingRx + NonBlockingTx,
+{
+    /// Constructor. Takes ownership of both connections.
+    pub fn new(a: FramedConnection<A>, b: FramedConnection<B>) -> Translator<A, B> {
+        Translator { a, b, dropped: 0 }
+    }
+
+    /// The first connection.
+    pub fn a(&self) -> &FramedConnection<A> {
+        &self.a
+    }
+
+    /// The second connection.
+    pub fn b(&self) -> &FramedConnection<B> {
+        &self.b
+    }
+
+    /// The number of received frames dropped because of a wrong checksum.
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Return both connections.
+    pub fn into_inner(self) -> (FramedConnection<A>, FramedConnection<B>) {
+        (self.a, self.b)
+    }
+
+    /// Service both connections and forward completed frames.
+    ///
+    /// Returns the number of frames forwarded. A frame received with a wrong
+    /// checksum is dropped and counted in `dropped_frames()`, and the other
+    /// direction is still served. If the other connection refuses a frame,
+    /// for example because it is too long for its framing mode, the frame
+    /// is dropped and the error is returned.
+    pub fn tick(&mut self) -> Result<usize> {
+        let progress_a = self.a.tick()?;
+        let progress_b = self.b.tick()?;
+        let mut n_forwarded = 0;
+        if progress_a.recv_is_done && progress_b.send_is_done {
+            if let Some(frame) = take_frame(&mut self.a, &mut self.dropped)? {
+                self.b.schedule_send(frame)?;
+                n_forwarded += 1;
+            }
+        }
+        if progress_b.recv_is_done && progress_a.send_is_done {
+            if let Some(frame) = take_frame(&mut self.b, &mut self.dropped)? {
+                self.a.schedule_send(frame)?;
+                n_forwarded += 1;
+            }
+        }
+        Ok(n_forwarded)
+    }
+}
+
+/// Take the received frame of `conn`, counting it in `dropped` if its
+/// checksum is wrong.
+fn take_frame<S>(conn: &mut FramedConnection<S>, dropped: &mut u64) -> Result<Option<Vec<u8>>>
+    where S: NonBlockingRx + NonBlockingTx,
+{
+    match conn.get_frame() {
+        Ok(frame) => Ok(Some(frame)),
+        Err(ref e) if e.kind() == ErrorKind::ChecksumMismatch => {
+            *dropped = dropped.saturating_add(1);
+            Ok(None)
+        },
+        Err(e) => Err(e),
+    }
+}
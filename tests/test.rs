@@ -1,7 +1,18 @@
 extern crate embedded_serial;
 extern crate framed_serial;
 
-use framed_serial::FramedConnection;
+use framed_serial::{encode_frame, encode_frame_with, BigEndian, Direction, Endianness, ErrorKind, FragmentedConnection,
+                    FrameConfig, FrameDecoder, FrameOutcome, FramedConnection, FramedConnectionBuilder, FrameTransport, FramingMode, HealthThresholds,
+                    LengthWidth, LineStatus, LinkStatus, LittleEndian, NativeEndian, ReliableConnection, SlipConnection, Stats, Translator,
+                    ESC, MAX_MAGIC_LEN};
+
+#[cfg(feature = "test-util")]
+use framed_serial::{loopback, BenchSerial, ByteTransform, QueueOrder, RecvProgress, SendProgress};
+
+use std::cell::Cell;
+#[cfg(feature = "collision-detection")]
+use std::cell::RefCell;
+use std::rc::Rc;
 
 struct MockSerial {
     in_flight: Vec<u8>,
@@ -16,7 +27,7 @@ impl MockSerial {
 impl embedded_serial::NonBlockingRx for MockSerial {
     type Error=();
     fn getc_try(&mut self) -> Result<Option<u8>, Self::Error> {
-        if self.in_flight.len() < 1 {
+        if self.in_flight.is_empty() {
             return Ok(None);
         }
         Ok( Some(self.in_flight.remove(0)) )
@@ -31,13 +42,132 @@ impl embedded_serial::NonBlockingTx for MockSerial {
     }
 }
 
+enum LineEvent {
+    Byte(u8),
+    Idle,
+    Break,
+    /// The device was closed; every further read fails.
+    Closed,
+}
+
+/// A serial device replaying a fixed sequence of line events.
+struct ScriptedSerial {
+    events: Vec<LineEvent>,
+}
+
+impl embedded_serial::NonBlockingRx for ScriptedSerial {
+    type Error=();
+    fn getc_try(&mut self) -> Result<Option<u8>, Self::Error> {
+        match self.events.first() {
+            Some(&LineEvent::Byte(byte)) => {
+                self.events.remove(0);
+                Ok(Some(byte))
+            },
+            Some(&LineEvent::Idle) => {
+                self.events.remove(0);
+                Ok(None)
+            },
+            Some(&LineEvent::Break) | None => Ok(None),
+            Some(&LineEvent::Closed) => Err(()),
+        }
+    }
+}
+
+impl embedded_serial::NonBlockingTx for ScriptedSerial {
+    type Error=();
+    fn putc_try(&mut self, ch: u8) -> Result<Option<u8>, Self::Error> {
+        Ok(Some(ch))
+    }
+}
+
+impl LineStatus for ScriptedSerial {
+    fn take_break(&mut self) -> bool {
+        if let Some(&LineEvent::Break) = self.events.first() {
+            self.events.remove(0);
+            return true;
+        }
+        false
+    }
+}
+
+/// A loopback serial device whose clear-to-send line is set by the test.
+struct FlowSerial {
+    inner: MockSerial,
+    cts: Rc<Cell<bool>>,
+}
+
+impl embedded_serial::NonBlockingRx for FlowSerial {
+    type Error=();
+    fn getc_try(&mut self) -> Result<Option<u8>, Self::Error> {
+        self.inner.getc_try()
+    }
+}
+
+impl embedded_serial::NonBlockingTx for FlowSerial {
+    type Error=();
+    fn putc_try(&mut self, ch: u8) -> Result<Option<u8>, Self::Error> {
+        assert!(self.cts.get(), "putc_try() while not clear to send");
+        self.inner.putc_try(ch)
+    }
+}
+
+impl LineStatus for FlowSerial {
+    fn is_clear_to_send(&mut self) -> bool {
+        self.cts.get()
+    }
+}
+
+/// A transmit-only device on a shared bus which reports one collision.
+#[cfg(feature = "collision-detection")]
+struct BusSerial {
+    written: Rc<RefCell<Vec<u8>>>,
+    collide_at: Option<usize>,
+    collided: bool,
+}
+
+#[cfg(feature = "collision-detection")]
+impl embedded_serial::NonBlockingRx for BusSerial {
+    type Error=();
+    fn getc_try(&mut self) -> Result<Option<u8>, Self::Error> {
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "collision-detection")]
+impl embedded_serial::NonBlockingTx for BusSerial {
+    type Error=();
+    fn putc_try(&mut self, ch: u8) -> Result<Option<u8>, Self::Error> {
+        let mut written = self.written.borrow_mut();
+        written.push(ch);
+        if self.collide_at == Some(written.len()) {
+            self.collided = true;
+        }
+        Ok(Some(ch))
+    }
+}
+
+#[cfg(feature = "collision-detection")]
+impl LineStatus for BusSerial {
+    fn last_tx_collided(&mut self) -> bool {
+        let collided = self.collided;
+        self.collided = false;
+        collided
+    }
+}
+
 fn test_buffer(original: &[u8]) {
     let mut conn = FramedConnection::new(MockSerial::new());
+    test_buffer_on(&mut conn, original);
+}
+
+fn test_buffer_on(conn: &mut FramedConnection<MockSerial>, original: &[u8]) {
     conn.schedule_send(original.to_vec()).unwrap();
     loop {
         let tick_state = conn.tick();
         if tick_state.unwrap().recv_is_done {
+            assert!(conn.frame_ready());
             let valid = conn.get_frame().unwrap();
+            assert!(!conn.frame_ready());
             assert!(valid == original);
 
             // because this is just a test, we break here
@@ -51,8 +181,2434 @@ fn test_1() {
     test_buffer(b"123");
 }
 
-#[cfg(feature = "std")]
-extern crate serial;
+#[test]
+fn test_tx_chunk_yield() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_tx_chunk_yield(Some(3));
+    // 1 sentinel + 2 header + 5 data bytes = 8 bytes on the wire.
+    conn.schedule_send(b"12345".to_vec()).unwrap();
+    assert!(!conn.tick().unwrap().send_is_done);
+    assert!(!conn.tick().unwrap().send_is_done);
+    let tick_state = conn.tick().unwrap();
+    assert!(tick_state.send_is_done);
+    assert!(tick_state.recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"12345");
+}
+
+#[test]
+fn test_accept_sentinels() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_accept_sentinels(&[0xFF, 0xAA]).unwrap();
+    for &sentinel in &[0xAA, 0xFF] {
+        conn.set_sentinel(sentinel).unwrap();
+        conn.schedule_send(b"abc".to_vec()).unwrap();
+        loop {
+            if conn.tick().unwrap().recv_is_done {
+                break;
+            }
+        }
+        let info = conn.get_frame_info().unwrap();
+        assert!(info.data == b"abc");
+        assert_eq!(info.sentinel, sentinel);
+    }
+}
+
+#[test]
+fn test_idle_byte() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0x00, 0x00, 0x12, 0x00, 0xFF, 3, 0, b'a', b'b', b'c'];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_idle_byte(Some(0x00));
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"abc");
+    assert_eq!(conn.discarded_bytes(), 1);
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_bench_serial_backpressure() {
+    let original = b"a frame larger than the transmit fifo";
+    let mut ser = BenchSerial::new(4, 2);
+    ser.set_rx_feed_rate(Some(3));
+    let mut conn = FramedConnection::new(ser);
+    conn.schedule_send(original.to_vec()).unwrap();
+    let mut n_ticks = 0;
+    loop {
+        n_ticks += 1;
+        if conn.tick().unwrap().recv_is_done {
+            break;
+        }
+    }
+    assert!(n_ticks > 1);
+    assert!(conn.get_frame().unwrap() == original[..]);
+}
+
+#[test]
+fn test_listen_only() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.schedule_send(b"abc".to_vec()).unwrap();
+    conn.set_listen_only(true);
+    assert!(conn.schedule_send(b"abc".to_vec()).is_err());
+    let tick_state = conn.tick().unwrap();
+    assert!(tick_state.send_is_done);
+    assert!(!tick_state.recv_is_done);
+}
+
+#[test]
+fn test_listen_only_discards_queue() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.schedule_send(b"a".to_vec()).unwrap();
+    conn.schedule_send(b"b".to_vec()).unwrap();
+    assert_eq!(conn.pending_send_count(), 2);
+    conn.set_listen_only(true);
+    conn.set_listen_only(false);
+    assert_eq!(conn.pending_send_count(), 0);
+    assert!(!conn.is_sending());
+    conn.block_until_send_done().unwrap();
+    let (_, state) = conn.finalize();
+    assert!(state.unsent.is_none());
+    assert!(state.queued.is_empty());
+}
+
+#[test]
+fn test_eot() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    assert_eq!(conn.schedule_eot().unwrap_err().kind(), ErrorKind::InvalidFrame);
+    // an empty frame is not the end of transmission by default
+    conn.schedule_send(Vec::new()).unwrap();
+    conn.tick().unwrap();
+    assert!(!conn.get_frame_info().unwrap().is_eot);
+
+    conn.set_eot_marker(Some(b"EOT"));
+    conn.schedule_send(b"abc".to_vec()).unwrap();
+    conn.tick().unwrap();
+    assert!(!conn.get_frame_info().unwrap().is_eot);
+    conn.schedule_eot().unwrap();
+    assert!(conn.tick().unwrap().recv_is_done);
+    let info = conn.get_frame_info().unwrap();
+    assert!(info.is_eot);
+    assert!(info.data == b"EOT");
+}
+
+#[test]
+fn test_break_resyncs() {
+    use LineEvent::*;
+    let events = vec![Byte(0xFF), Byte(5), Byte(0), Byte(b'a'), Byte(b'b'), Idle,
+                      Break, Byte(0xFF), Byte(1), Byte(0), Byte(b'z')];
+    let mut conn = FramedConnection::new(ScriptedSerial { events });
+    assert!(!conn.tick_with_line_status().unwrap().recv_is_done);
+    assert!(conn.tick_with_line_status().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"z");
+    assert_eq!(conn.breaks_detected(), 1);
+    assert_eq!(conn.resync_count(), 1);
+}
+
+#[test]
+fn test_resync_count() {
+    let mut ser = MockSerial::new();
+    // startup garbage does not count, garbage after a good frame does
+    ser.in_flight = vec![0x12, 0xFF, 1, 0, b'a', 0x00, 0x34, 0xFF, 1, 0, b'b'];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_idle_byte(Some(0x00));
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"a");
+    assert!(conn.get_frame().unwrap() == b"b");
+    assert_eq!(conn.resync_count(), 1);
+    assert_eq!(conn.discarded_bytes(), 2);
+}
+
+#[test]
+fn test_recv_soft_limit() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_recv_soft_limit(Some(3));
+    test_buffer_on(&mut conn, b"123");
+    assert_eq!(conn.large_frames(), 0);
+    test_buffer_on(&mut conn, b"123456");
+    assert_eq!(conn.large_frames(), 1);
+
+    // chunks of a reassembled frame count together
+    conn.set_auto_chunk(Some(2));
+    test_buffer_on(&mut conn, b"abcdefghij");
+    assert_eq!(conn.large_frames(), 2);
+    test_buffer_on(&mut conn, b"abc");
+    assert_eq!(conn.large_frames(), 2);
+}
+
+/// XOR with a key which advances after every byte.
+#[cfg(feature = "test-util")]
+struct XorTransform {
+    encode_key: u8,
+    decode_key: u8,
+}
+
+#[cfg(feature = "test-util")]
+impl ByteTransform for XorTransform {
+    fn encode(&mut self, byte: u8) -> u8 {
+        self.encode_key = self.encode_key.wrapping_add(1);
+        byte ^ self.encode_key
+    }
+    fn decode(&mut self, byte: u8) -> u8 {
+        self.decode_key = self.decode_key.wrapping_add(1);
+        byte ^ self.decode_key
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_byte_transform() {
+    // A small FIFO makes sure bytes rejected by the transmitter are not
+    // encoded twice.
+    let mut conn = FramedConnection::new(BenchSerial::new(2, 1));
+    conn.set_byte_transform(Some(Box::new(XorTransform { encode_key: 0x5A, decode_key: 0x5A })));
+    conn.schedule_send(b"\xFFsecret".to_vec()).unwrap();
+    loop {
+        if conn.tick().unwrap().recv_is_done {
+            break;
+        }
+    }
+    assert!(conn.get_frame().unwrap() == b"\xFFsecret");
+}
+
+#[test]
+fn test_inter_frame_gap() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_tx_inter_frame_gap_ticks(2);
+    conn.schedule_send(b"1".to_vec()).unwrap();
+    assert!(conn.tick().unwrap().send_is_done);
+    assert!(conn.get_frame().unwrap() == b"1");
+    conn.schedule_send(b"2".to_vec()).unwrap();
+    assert!(!conn.tick().unwrap().send_is_done);
+    assert!(!conn.tick().unwrap().send_is_done);
+    assert!(conn.tick().unwrap().send_is_done);
+    assert!(conn.get_frame().unwrap() == b"2");
+}
+
+#[test]
+fn test_turnaround() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_turnaround_ticks(2);
+    // nothing received yet, so the first frame goes out at once
+    conn.schedule_send(b"1".to_vec()).unwrap();
+    assert!(conn.tick().unwrap().send_is_done);
+    assert!(conn.get_frame().unwrap() == b"1");
+    conn.schedule_send(b"2".to_vec()).unwrap();
+    assert!(!conn.tick().unwrap().send_is_done);
+    assert!(!conn.tick().unwrap().send_is_done);
+    assert!(conn.tick().unwrap().send_is_done);
+    assert!(conn.get_frame().unwrap() == b"2");
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_from_split() {
+    let mut rx = MockSerial::new();
+    rx.in_flight = vec![0xFF, 3, 0, b'a', b'b', b'c'];
+    // a transmitter which never drains, so sent bytes cannot loop back
+    let tx = BenchSerial::new(16, 0);
+    let mut conn = FramedConnection::from_split(rx, tx);
+    conn.schedule_send(b"xy".to_vec()).unwrap();
+    let progress = conn.tick().unwrap();
+    assert!(progress.send_is_done);
+    assert!(progress.recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"abc");
+}
+
+fn echo_once<T: FrameTransport>(transport: &mut T, frame: &[u8]) -> Vec<u8> {
+    transport.schedule_frame(frame.to_vec()).unwrap();
+    loop {
+        transport.tick().unwrap();
+        if let Some(frame) = transport.try_get_frame().unwrap() {
+            return frame;
+        }
+    }
+}
+
+#[test]
+fn test_frame_transport() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    assert!(conn.try_get_frame().unwrap().is_none());
+    assert!(echo_once(&mut conn, b"abc") == b"abc");
+    assert!(conn.try_get_frame().unwrap().is_none());
+}
+
+#[test]
+fn test_idle_tick_warning() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_idle_tick_warn_limit(Some(2));
+    let warnings: Vec<bool> = (0..4).map(|_| conn.tick().unwrap().idle_warning).collect();
+    assert_eq!(warnings, vec![false, false, true, true]);
+    conn.schedule_send(b"abc".to_vec()).unwrap();
+    assert!(!conn.tick().unwrap().idle_warning);
+    conn.get_frame().unwrap();
+    assert!(!conn.tick().unwrap().idle_warning);
+}
+
+#[test]
+fn test_clear_to_send() {
+    let cts = Rc::new(Cell::new(false));
+    let ser = FlowSerial { inner: MockSerial::new(), cts: cts.clone() };
+    let mut conn = FramedConnection::new(ser);
+    conn.schedule_send(b"abc".to_vec()).unwrap();
+    for _ in 0..3 {
+        let progress = conn.tick_with_line_status().unwrap();
+        assert!(!progress.send_is_done);
+        assert!(!progress.recv_is_done);
+    }
+    cts.set(true);
+    let progress = conn.tick_with_line_status().unwrap();
+    assert!(progress.send_is_done);
+    assert!(progress.recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"abc");
+}
+
+#[cfg(feature = "capture")]
+#[test]
+fn test_last_tx_wire_bytes() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    assert!(conn.last_tx_wire_bytes().is_none());
+    test_buffer_on(&mut conn, b"ab");
+    assert_eq!(conn.last_tx_wire_bytes().unwrap(), &[0xFF, 2, 0, b'a', b'b']);
+    test_buffer_on(&mut conn, b"");
+    assert_eq!(conn.last_tx_wire_bytes().unwrap(), &[0xFF, 0, 0]);
+    conn.set_endianness(Endianness::Big);
+    test_buffer_on(&mut conn, b"ab");
+    assert_eq!(conn.last_tx_wire_bytes().unwrap(), &[0xFF, 0, 2, b'a', b'b']);
+}
+
+#[cfg(feature = "collision-detection")]
+#[test]
+fn test_collision_backoff() {
+    let written = Rc::new(RefCell::new(Vec::new()));
+    let ser = BusSerial { written: written.clone(), collide_at: Some(2), collided: false };
+    let mut conn = FramedConnection::new(ser);
+    conn.set_tx_chunk_yield(Some(2));
+    conn.set_collision_backoff(1, 1234);
+    conn.schedule_send(b"abcd".to_vec()).unwrap();
+    assert!(!conn.tick_with_line_status().unwrap().send_is_done);
+    // the collision is noticed and the sender backs off for one tick
+    assert!(!conn.tick_with_line_status().unwrap().send_is_done);
+    assert_eq!(conn.collisions(), 1);
+    assert_eq!(written.borrow().len(), 2);
+    while !conn.tick_with_line_status().unwrap().send_is_done {}
+    assert_eq!(*written.borrow(), vec![0xFF, 4, 0xFF, 4, 0, b'a', b'b', b'c', b'd']);
+    assert_eq!(conn.collisions(), 1);
+}
+
+#[cfg(feature = "collision-detection")]
+#[test]
+fn test_collision_within_one_tick() {
+    // the whole frame fits in one tick, and collides after its second data byte
+    let written = Rc::new(RefCell::new(Vec::new()));
+    let ser = BusSerial { written: written.clone(), collide_at: Some(5), collided: false };
+    let mut conn = FramedConnection::new(ser);
+    conn.set_collision_backoff(1, 1234);
+    conn.schedule_send(b"abcd".to_vec()).unwrap();
+    assert!(!conn.tick_with_line_status().unwrap().send_is_done);
+    assert_eq!(conn.collisions(), 1);
+    assert_eq!(conn.last_tx_index(), None);
+    for _ in 0..4 {
+        conn.tick_with_line_status().unwrap();
+    }
+    assert_eq!(*written.borrow(), vec![0xFF, 4, 0, b'a', b'b', 0xFF, 4, 0, b'a', b'b', b'c', b'd']);
+    assert_eq!(conn.collisions(), 1);
+    assert_eq!(conn.last_tx_index(), Some(0));
+
+    // only the chunk which collided is sent again
+    let written = Rc::new(RefCell::new(Vec::new()));
+    let ser = BusSerial { written: written.clone(), collide_at: Some(9), collided: false };
+    let mut conn = FramedConnection::new(ser);
+    conn.set_auto_chunk(Some(2));
+    conn.set_collision_backoff(1, 1234);
+    conn.schedule_send(b"abcd".to_vec()).unwrap();
+    for _ in 0..5 {
+        conn.tick_with_line_status().unwrap();
+    }
+    assert_eq!(*written.borrow(), vec![0xFF, 2, 0, 1, b'a', b'b', 0xFF, 2, 0,
+                                       0xFF, 2, 0, 0, b'c', b'd']);
+    assert_eq!(conn.collisions(), 1);
+    assert!(!conn.is_sending());
+    #[cfg(feature = "capture")]
+    assert_eq!(conn.last_tx_wire_bytes().unwrap(), &[0xFF, 2, 0, 1, b'a', b'b', 0xFF, 2, 0, 0, b'c', b'd']);
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_finalize() {
+    // a transmit FIFO of four bytes which never drains
+    let mut ser = BenchSerial::new(4, 0);
+    ser.feed_rx(&[0xFF, 1, 0, b'a']);
+    let mut conn = FramedConnection::new(ser);
+    conn.schedule_send(b"wxyz".to_vec()).unwrap();
+    conn.tick().unwrap();
+    let (_, state) = conn.finalize();
+    let unsent = state.unsent.unwrap();
+    assert_eq!(unsent.bytes_sent, 1);
+    assert_eq!(unsent.remainder(), b"xyz");
+    assert_eq!(state.received.len(), 1);
+    assert_eq!(state.received[0].data, b"a");
+    assert!(state.partial_recv.is_empty());
+
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 3, 0, b'b', b'c'];
+    let mut conn = FramedConnection::new(ser);
+    conn.tick().unwrap();
+    let (_, state) = conn.finalize();
+    assert!(state.unsent.is_none());
+    assert!(state.received.is_empty());
+    assert_eq!(state.partial_recv, b"bc");
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_bench_serial_baud() {
+    // 1 ms per byte
+    let ser = BenchSerial::new(16, 0).with_baud(10_000);
+    let mut conn = FramedConnection::new(ser);
+    conn.schedule_send(b"abc".to_vec()).unwrap();
+    let mut ticks = 0;
+    while !conn.tick().unwrap().recv_is_done {
+        ticks += 1;
+        assert!(ticks < 100);
+    }
+    assert!(conn.get_frame().unwrap() == b"abc");
+    let (ser, _) = conn.finalize();
+    // six bytes on the wire cannot arrive sooner than 6 ms
+    assert!(ser.virtual_now() >= std::time::Duration::from_millis(6));
+}
+
+#[cfg(feature = "capture")]
+#[test]
+fn test_translator() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 1, 0, b'x'];
+    let a = FramedConnection::new(ser);
+    let mut b = FramedConnection::new(MockSerial::new());
+    b.set_sentinel(0x7E).unwrap();
+    let mut translator = Translator::new(a, b);
+    assert_eq!(translator.tick().unwrap(), 1);
+    translator.tick().unwrap();
+    assert_eq!(translator.b().last_tx_wire_bytes().unwrap(), &[0x7E, 1, 0, b'x']);
+}
+
+#[test]
+fn test_translator_drops_corrupt_frames() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 3, 0, b'b', b'a', b'd', 0, 0];
+    let mut a = FramedConnection::new(ser);
+    a.set_checksum(true);
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 1, 0, b'y'];
+    let b = FramedConnection::new(ser);
+    let mut translator = Translator::new(a, b);
+    // the corrupt frame on a does not hold up the frame on b
+    assert_eq!(translator.tick().unwrap(), 1);
+    assert_eq!(translator.dropped_frames(), 1);
+    assert_eq!(translator.a().checksum_errors(), 1);
+}
+
+#[test]
+fn test_checksum() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_checksum(true);
+    assert_eq!(conn.wire_len(3), 8);
+    test_buffer_on(&mut conn, b"");
+    test_buffer_on(&mut conn, b"123");
+    test_buffer_on(&mut conn, &[0xFF; 100]);
+    conn.set_auto_chunk(Some(16));
+    test_buffer_on(&mut conn, &(0..100).collect::<Vec<u8>>());
+    assert_eq!(conn.checksum_errors(), 0);
+
+    // a corrupted frame followed by a good one
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 1, 0, b'b', 0x2B, 0x87, 0xFF, 1, 0, b'a', 0x2B, 0x87];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_checksum(true);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().is_err());
+    assert_eq!(conn.checksum_errors(), 1);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"a");
+}
+
+#[test]
+fn test_checksum_reported() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 1, 0, b'a', 0x2B, 0x87];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_checksum(true);
+    assert!(conn.tick().unwrap().recv_is_done);
+    let info = conn.get_frame_info().unwrap();
+    assert_eq!(info.data, b"a");
+    assert_eq!(info.crc, Some(0x872B));
+
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 1, 0, b'a'];
+    let mut conn = FramedConnection::new(ser);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert_eq!(conn.get_frame_info().unwrap().crc, None);
+}
+
+#[test]
+fn test_send_with_crc() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    assert_eq!(conn.schedule_send_with_crc(b"a".to_vec(), 0).unwrap_err().kind(), ErrorKind::InvalidFrame);
+    conn.set_checksum(true);
+    // the correct checksum is received
+    conn.schedule_send_with_crc(b"a".to_vec(), 0x872B).unwrap();
+    conn.block_until_send_done().unwrap();
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert_eq!(conn.get_frame_info().unwrap().crc, Some(0x872B));
+    // a wrong one is dropped
+    conn.schedule_send_with_crc(b"a".to_vec(), 0x1234).unwrap();
+    conn.block_until_send_done().unwrap();
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert_eq!(conn.get_frame().unwrap_err().kind(), ErrorKind::ChecksumMismatch);
+    assert_eq!(conn.checksum_errors(), 1);
+}
+
+#[cfg(feature = "capture")]
+#[test]
+fn test_checksum_value() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_checksum(true);
+    conn.set_framing_mode(FramingMode::FixedSize(9));
+    test_buffer_on(&mut conn, b"123456789");
+    let mut expected = vec![0xFF];
+    expected.extend_from_slice(b"123456789");
+    expected.extend_from_slice(&[0xB1, 0x29]);
+    assert_eq!(conn.last_tx_wire_bytes().unwrap(), &expected[..]);
+}
+
+#[test]
+fn test_escaping() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_escaping(true).unwrap();
+    test_buffer_on(&mut conn, &[0xFF; 10]);
+    test_buffer_on(&mut conn, &[ESC; 10]);
+    test_buffer_on(&mut conn, &[ESC, 0xFF, 0x01, ESC ^ 0xFF, 0xFF, ESC]);
+    // a length containing the sentinel
+    test_buffer_on(&mut conn, &vec![0xAA; 0x1FF]);
+    conn.set_checksum(true);
+    test_buffer_on(&mut conn, &(0..=255).collect::<Vec<u8>>());
+    conn.set_framing_mode(FramingMode::FixedSize(2));
+    test_buffer_on(&mut conn, &[0xFF, ESC]);
+}
+
+#[test]
+fn test_escaping_rejects_esc_sentinel() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_escaping(true).unwrap();
+    assert_eq!(conn.set_sentinel(ESC).unwrap_err().kind(), ErrorKind::InvalidFrame);
+    assert_eq!(conn.set_accept_sentinels(&[0xFF, ESC]).unwrap_err().kind(), ErrorKind::InvalidFrame);
+    test_buffer_on(&mut conn, &[ESC, 0xFF]);
+
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_sentinel(ESC).unwrap();
+    assert_eq!(conn.set_escaping(true).unwrap_err().kind(), ErrorKind::InvalidFrame);
+    test_buffer_on(&mut conn, &[ESC, 0xFF]);
+    let builder = FramedConnectionBuilder::new().sentinel(ESC).escaping(true);
+    assert_eq!(builder.build(MockSerial::new()).err().unwrap().kind(), ErrorKind::InvalidFrame);
+}
+
+#[cfg(feature = "capture")]
+#[test]
+fn test_escaping_wire_bytes() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_escaping(true).unwrap();
+    test_buffer_on(&mut conn, &[1, 0xFF, ESC]);
+    let wire = conn.last_tx_wire_bytes().unwrap();
+    assert_eq!(wire, &[0xFF, 3, 0, 1, ESC, 0xFF ^ ESC, ESC, 0]);
+    assert!(!wire[1..].contains(&0xFF));
+}
+
+#[test]
+fn test_send_queue() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_tx_chunk_yield(Some(4));
+    conn.schedule_send(b"one".to_vec()).unwrap();
+    conn.schedule_send(b"two".to_vec()).unwrap();
+    conn.schedule_send(b"three".to_vec()).unwrap();
+    assert_eq!(conn.pending_send_count(), 3);
+    assert!(conn.schedule_send(vec![0; u16::MAX as usize + 1]).is_err());
+    assert_eq!(conn.pending_send_count(), 3);
+    let mut received = Vec::new();
+    while received.len() < 3 {
+        if conn.tick().unwrap().recv_is_done {
+            received.push(conn.get_frame().unwrap());
+        }
+    }
+    assert_eq!(received, vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]);
+    assert_eq!(conn.pending_send_count(), 0);
+}
+
+#[test]
+fn test_send_queue_back_to_back() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    // keep the sent bytes in the device
+    conn.pause_recv();
+    let frames: [&[u8]; 4] = [b"one", b"", b"two", b"three"];
+    for frame in frames.iter() {
+        conn.schedule_send(frame.to_vec()).unwrap();
+    }
+    let progress = conn.tick().unwrap();
+    assert!(progress.send_is_done);
+    // one pass, with nothing between one frame and the next sentinel
+    let expected: Vec<u8> = frames.iter().flat_map(|frame| encode_frame(frame).unwrap()).collect();
+    assert_eq!(progress.bytes_sent, expected.len());
+    assert_eq!(conn.get_ref().in_flight, expected);
+}
+
+#[test]
+fn test_error_kind() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    let err = conn.get_frame().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::FrameNotAvailable);
+    assert_eq!(err.to_string(), "Error: frame not available");
+    let err = conn.schedule_send(vec![0; u16::MAX as usize + 1]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::FrameTooLong);
+    conn.set_framing_mode(FramingMode::FixedSize(2));
+    let err = conn.schedule_send(vec![0; 3]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WrongFrameSize);
+    conn.set_listen_only(true);
+    let err = conn.schedule_send(vec![0; 2]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ListenOnly);
+
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 1, 0, b'b', 0x2B, 0x87];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_checksum(true);
+    conn.tick().unwrap();
+    assert_eq!(conn.get_frame().unwrap_err().kind(), ErrorKind::ChecksumMismatch);
+
+    let err = framed_serial::Error::new("custom".into());
+    assert_eq!(err.kind(), ErrorKind::Other);
+    assert_eq!(err.detail(), Some("custom"));
+    assert_eq!(err.to_string(), "Error: custom");
+}
+
+#[test]
+fn test_peek_frame() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 2];
+    let mut conn = FramedConnection::new(ser);
+    assert!(conn.peek_frame().is_none());
+    // in the header
+    conn.tick().unwrap();
+    assert!(conn.peek_frame().is_none());
+    // in the data
+    conn.schedule_send(b"".to_vec()).unwrap();
+    conn.tick().unwrap();
+    assert!(conn.peek_frame().is_none());
+
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 2, 0, b'a', b'b'];
+    let mut conn = FramedConnection::new(ser);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert_eq!(conn.peek_frame(), Some(&b"ab"[..]));
+    assert_eq!(conn.peek_frame(), Some(&b"ab"[..]));
+    assert!(conn.get_frame().unwrap() == b"ab");
+    assert!(conn.peek_frame().is_none());
+}
+
+#[test]
+fn test_into_inner() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_tx_chunk_yield(Some(2));
+    conn.pause_recv();
+    conn.schedule_send(b"abc".to_vec()).unwrap();
+    conn.tick().unwrap();
+    let ser = conn.into_inner();
+    assert_eq!(ser.in_flight, vec![0xFF, 3]);
+
+    let mut rx = MockSerial::new();
+    rx.in_flight = vec![1];
+    let conn = FramedConnection::from_split(rx, MockSerial::new());
+    let (rx, _) = conn.into_inner().into_inner();
+    assert_eq!(rx.in_flight, vec![1]);
+}
+
+#[test]
+fn test_get_ref_get_mut() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.get_mut().in_flight.extend_from_slice(&[0xFF, 1, 0, b'a']);
+    assert_eq!(conn.get_ref().in_flight.len(), 4);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"a");
+    assert!(conn.get_ref().in_flight.is_empty());
+}
+
+#[test]
+fn test_abort_recv() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 10, 0, b'a', b'b'];
+    let mut conn = FramedConnection::new(ser);
+    assert!(!conn.tick().unwrap().recv_is_done);
+    conn.abort_recv();
+    conn.get_mut().in_flight.extend_from_slice(&[0xFF, 2, 0, b'c', b'd']);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"cd");
+}
+
+#[test]
+fn test_abort_send() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    assert!(conn.abort_send().is_none());
+    conn.set_tx_chunk_yield(Some(2));
+    conn.pause_recv();
+    conn.schedule_send(b"abc".to_vec()).unwrap();
+    conn.schedule_send(b"d".to_vec()).unwrap();
+    assert!(!conn.tick().unwrap().send_is_done);
+    assert_eq!(conn.abort_send(), Some(b"abc".to_vec()));
+    assert_eq!(conn.pending_send_count(), 1);
+    conn.block_until_send_done().unwrap();
+    assert_eq!(conn.get_ref().in_flight, vec![0xFF, 3, 0xFF, 1, 0, b'd']);
+}
+
+#[test]
+fn test_is_sending_is_receiving() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    assert!(!conn.is_sending());
+    assert!(!conn.is_receiving());
+    conn.set_tx_chunk_yield(Some(2));
+    conn.schedule_send(b"ab".to_vec()).unwrap();
+    assert!(conn.is_sending());
+    conn.tick().unwrap();
+    assert!(conn.is_sending());
+    assert!(conn.is_receiving());
+    while !conn.tick().unwrap().recv_is_done {}
+    assert!(!conn.is_sending());
+    // complete, so no longer receiving
+    assert!(!conn.is_receiving());
+    assert!(conn.get_frame().unwrap() == b"ab");
+}
+
+#[test]
+fn test_recv_into() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 3, 0, b'a', b'b', b'c'];
+    let mut conn = FramedConnection::new(ser);
+    let mut buf = [0; 8];
+    assert_eq!(conn.recv_into(&mut buf).unwrap_err().kind(), ErrorKind::FrameNotAvailable);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert_eq!(conn.recv_into(&mut buf[..2]).unwrap_err().kind(), ErrorKind::BufferTooSmall);
+    assert_eq!(conn.recv_into(&mut buf).unwrap(), 3);
+    assert_eq!(&buf[..3], b"abc");
+    assert!(!conn.frame_ready());
+    test_buffer_on(&mut conn, b"next");
+
+    // a partially received frame is left alone
+    conn.get_mut().in_flight = vec![0xFF, 2, 0, b'x'];
+    conn.tick().unwrap();
+    assert_eq!(conn.recv_into(&mut buf).unwrap_err().kind(), ErrorKind::FrameNotAvailable);
+    conn.get_mut().in_flight = vec![b'y'];
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert_eq!(conn.recv_into(&mut buf).unwrap(), 2);
+    assert_eq!(&buf[..2], b"xy");
+}
+
+#[test]
+fn test_length_width() {
+    let cases = [
+        (LengthWidth::U8, 1, 255, Some(256)),
+        (LengthWidth::U16, 2, 65_535, Some(65_536)),
+        (LengthWidth::U32, 4, 100_000, None),
+    ];
+    for &(width, width_len, max_ok, too_long) in cases.iter() {
+        let mut conn = FramedConnection::new(MockSerial::new());
+        conn.set_length_width(width);
+        let original: Vec<u8> = (0..max_ok).map(|i| i as u8).collect();
+        test_buffer_on(&mut conn, &original);
+        test_buffer_on(&mut conn, b"");
+        assert_eq!(conn.wire_len(3), 4 + width_len);
+        if let Some(n) = too_long {
+            let err = conn.schedule_send(vec![0; n]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::FrameTooLong);
+        }
+    }
+
+    // With a 1 byte length, chunks are limited to 255 bytes.
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_length_width(LengthWidth::U8);
+    conn.set_auto_chunk(Some(1000));
+    let original: Vec<u8> = (0..600).map(|i| i as u8).collect();
+    assert_eq!(conn.wire_len(600), 3 * 3 + 600);
+    test_buffer_on(&mut conn, &original);
+}
+
+#[test]
+fn test_big_endian_length() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 0, 3, b'a', b'b', b'c'];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_endianness(Endianness::Big);
+    conn.tick().unwrap();
+    assert!(conn.get_frame().unwrap() == b"abc");
+
+    let original: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    test_buffer_on(&mut conn, &original);
+    conn.set_length_width(LengthWidth::U32);
+    test_buffer_on(&mut conn, &original);
+    conn.set_auto_chunk(Some(100));
+    test_buffer_on(&mut conn, &original);
+}
+
+#[test]
+fn test_varint_length() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_length_width(LengthWidth::Varint);
+    for &(len, varint_len) in [(0, 1), (127, 1), (128, 2), (16_383, 2), (16_384, 3)].iter() {
+        assert_eq!(conn.wire_len(len), 1 + varint_len + len);
+        let original: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        test_buffer_on(&mut conn, &original);
+    }
+    conn.set_auto_chunk(Some(200));
+    assert_eq!(conn.wire_len(500), 2 * 4 + 3 + 500);
+    let original: Vec<u8> = (0..500).map(|i| i as u8).collect();
+    test_buffer_on(&mut conn, &original);
+
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 0x80, 0x01];
+    ser.in_flight.extend((0..128).map(|i| i as u8));
+    let mut conn = FramedConnection::new(ser);
+    conn.set_length_width(LengthWidth::Varint);
+    conn.tick().unwrap();
+    assert_eq!(conn.get_frame().unwrap().len(), 128);
+}
+
+#[test]
+fn test_varint_length_overflow() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 0x80, 0x80, 0x80, 0x80, 0x80, 0xFF, 1, b'a'];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_length_width(LengthWidth::Varint);
+    conn.tick().unwrap();
+    assert_eq!(conn.resync_count(), 1);
+    conn.tick().unwrap();
+    assert!(conn.get_frame().unwrap() == b"a");
+}
+
+#[test]
+fn test_frames_iterator() {
+    let mut events: Vec<LineEvent> = [0xFF, 1, 0, b'a', 0xFF, 2, 0, b'b']
+        .iter().map(|&b| LineEvent::Byte(b)).collect();
+    events.push(LineEvent::Idle);
+    events.extend([b'c', 0xFF, 1, 0].iter().map(|&b| LineEvent::Byte(b)));
+    events.push(LineEvent::Closed);
+    let mut conn = FramedConnection::new(ScriptedSerial { events });
+    let frames: Vec<Vec<u8>> = conn.frames().map(|f| f.unwrap()).collect();
+    assert_eq!(frames, vec![b"a".to_vec(), b"bc".to_vec()]);
+    assert!(conn.is_receiving());
+}
+
+#[test]
+fn test_stats() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0x00, 0x01, 0xFF, 1, 0, b'a'];
+    let mut conn = FramedConnection::new(ser);
+    conn.tick().unwrap();
+    assert!(conn.get_frame().unwrap() == b"a");
+    test_buffer_on(&mut conn, b"ab");
+    let stats = conn.stats();
+    assert_eq!(stats.bytes_received, 6 + 5);
+    assert_eq!(stats.discarded_bytes, 2);
+    assert_eq!(stats.frames_received, 2);
+    assert_eq!(stats.bytes_sent, 5);
+    assert_eq!(stats.frames_sent, 1);
+
+    conn.reset_stats();
+    assert_eq!(conn.stats(), Stats::default());
+    assert_eq!(conn.discarded_bytes(), 2);
+    test_buffer_on(&mut conn, b"");
+    assert_eq!(conn.stats().bytes_sent, 3);
+    assert_eq!(conn.stats().frames_received, 1);
+}
+
+#[test]
+fn test_max_recv_len() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 4, 0, b'a', b'b', b'c', b'd'];
+    let mut conn = FramedConnection::new_with_max_recv_len(ser, 3);
+    conn.tick().unwrap();
+    assert!(!conn.frame_ready());
+    assert_eq!(conn.resync_count(), 1);
+    test_buffer_on(&mut conn, b"abc");
+
+    let mut conn = FramedConnection::new_with_max_recv_len(MockSerial::new(), 5);
+    conn.set_auto_chunk(Some(2));
+    test_buffer_on(&mut conn, b"abcde");
+    conn.schedule_send(b"abcdef".to_vec()).unwrap();
+    for _ in 0..10 {
+        conn.tick().unwrap();
+    }
+    assert!(!conn.frame_ready());
+    assert_eq!(conn.resync_count(), 1);
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_recv_timeout() {
+    let mut conn = FramedConnection::new(BenchSerial::new(64, 64));
+    conn.set_recv_timeout(Some(10));
+    // idle, so no timeout
+    conn.tick_with_now(100).unwrap();
+
+    conn.get_mut().feed_rx(&[0xFF, 3, 0, b'a']);
+    conn.tick_with_now(200).unwrap();
+    conn.tick_with_now(210).unwrap();
+    assert!(conn.is_receiving());
+    match conn.tick_with_now(211) {
+        Err(e) => assert_eq!(e.kind(), ErrorKind::FrameTimeout),
+        Ok(_) => panic!("expected a timeout"),
+    }
+    assert!(!conn.is_receiving());
+    assert_eq!(conn.resync_count(), 1);
+
+    conn.get_mut().feed_rx(&[0xFF, 1, 0, b'b']);
+    assert!(conn.tick_with_now(300).unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"b");
+
+    // the rest of the frame arrived before a late call
+    conn.get_mut().feed_rx(&[0xFF, 2, 0, b'c']);
+    conn.tick_with_now(400).unwrap();
+    conn.get_mut().feed_rx(b"d");
+    assert!(conn.tick_with_now(500).unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"cd");
+
+    // bytes received by plain tick() calls count as activity
+    conn.get_mut().feed_rx(&[0xFF, 2, 0]);
+    conn.tick_with_now(600).unwrap();
+    conn.get_mut().feed_rx(b"e");
+    conn.tick().unwrap();
+    conn.tick_with_now(700).unwrap();
+    assert!(conn.is_receiving());
+    conn.tick_with_now(710).unwrap();
+    match conn.tick_with_now(711) {
+        Err(e) => assert_eq!(e.kind(), ErrorKind::FrameTimeout),
+        Ok(_) => panic!("expected a timeout"),
+    }
+    assert_eq!(conn.resync_count(), 2);
+}
+
+#[test]
+fn test_escaping_resyncs_on_sentinel() {
+    let mut ser = MockSerial::new();
+    // a truncated frame, then a valid one
+    ser.in_flight = vec![0xFF, 5, 0, b'a', b'b', 0xFF, 2, 0, b'x', b'y'];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_escaping(true).unwrap();
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"xy");
+    assert_eq!(conn.resync_count(), 1);
+
+    // truncated within the header, as left by abort_send()
+    conn.get_mut().in_flight = vec![0xFF, 3, 0xFF, 1, 0, b'd'];
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"d");
+    assert_eq!(conn.resync_count(), 2);
+
+    // repeated idle sentinels do not count
+    conn.set_sentinel_is_idle(true);
+    conn.get_mut().in_flight = vec![0xFF, 0xFF, 0xFF, 1, 0, b'e'];
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"e");
+    assert_eq!(conn.resync_count(), 2);
+}
+
+#[test]
+fn test_tagged_frames() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    assert!(conn.schedule_send_tagged(1, b"a".to_vec()).is_err());
+    conn.set_tagging(true);
+    conn.pause_recv();
+    conn.schedule_send_tagged(7, b"ab".to_vec()).unwrap();
+    conn.schedule_send(b"c".to_vec()).unwrap();
+    conn.block_until_send_done().unwrap();
+    assert_eq!(conn.get_ref().in_flight, vec![0xFF, 7, 2, 0, b'a', b'b', 0xFF, 0, 1, 0, b'c']);
+    assert_eq!(conn.wire_len(2), 6);
+    conn.resume_recv();
+    conn.tick().unwrap();
+    assert_eq!(conn.get_tagged_frame().unwrap(), (7, b"ab".to_vec()));
+    conn.tick().unwrap();
+    assert_eq!(conn.get_frame_info().unwrap().tag, Some(0));
+
+    conn.set_checksum(true);
+    conn.set_auto_chunk(Some(2));
+    conn.schedule_send_tagged(0xFF, b"xyz".to_vec()).unwrap();
+    while !conn.tick().unwrap().recv_is_done {}
+    assert_eq!(conn.get_tagged_frame().unwrap(), (0xFF, b"xyz".to_vec()));
+}
+
+#[test]
+fn test_tag_bounds() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_tagging(true);
+    conn.set_tag_bounds(1, Some((2, 3)));
+    for frame in [&b"a"[..], b"abcd", b"ab"].iter() {
+        conn.schedule_send_tagged(1, frame.to_vec()).unwrap();
+    }
+    // frames with other tags are not checked
+    conn.schedule_send_tagged(2, b"a".to_vec()).unwrap();
+    conn.block_until_send_done().unwrap();
+    while conn.tick().unwrap().bytes_received > 0 {}
+    assert_eq!(conn.get_tagged_frame().unwrap(), (1, b"ab".to_vec()));
+    assert_eq!(conn.get_tagged_frame().unwrap(), (2, b"a".to_vec()));
+    assert_eq!(conn.tag_length_errors(), 2);
+    assert_eq!(conn.resync_count(), 2);
+
+    // the reassembled length is checked
+    conn.set_auto_chunk(Some(2));
+    conn.schedule_send_tagged(1, b"abcd".to_vec()).unwrap();
+    conn.schedule_send_tagged(1, b"abc".to_vec()).unwrap();
+    conn.block_until_send_done().unwrap();
+    while conn.tick().unwrap().bytes_received > 0 {}
+    assert_eq!(conn.get_tagged_frame().unwrap(), (1, b"abc".to_vec()));
+    assert!(conn.get_frame().is_err());
+    assert_eq!(conn.tag_length_errors(), 3);
+
+    conn.set_tag_bounds(1, None);
+    conn.schedule_send_tagged(1, b"a".to_vec()).unwrap();
+    conn.block_until_send_done().unwrap();
+    while conn.tick().unwrap().bytes_received > 0 {}
+    assert_eq!(conn.get_tagged_frame().unwrap(), (1, b"a".to_vec()));
+}
+
+#[test]
+fn test_reliable_connection() {
+    // over the loopback, every frame is acknowledged by ourselves
+    let mut rc = ReliableConnection::new(FramedConnection::new(MockSerial::new()), 10);
+    assert_eq!(rc.send_reliable(b"hi".to_vec()).unwrap(), 0);
+    assert_eq!(rc.send_reliable(b"yo".to_vec()).unwrap(), 1);
+    let mut delivered = Vec::new();
+    for _ in 0..10 {
+        if let Some(frame) = rc.poll().unwrap() {
+            delivered.push(frame);
+        }
+    }
+    assert_eq!(delivered, vec![b"hi".to_vec(), b"yo".to_vec()]);
+    assert_eq!(rc.unacked_count(), 0);
+    assert_eq!(rc.retransmits(), 0);
+
+    let mut rc = ReliableConnection::new(FramedConnection::new(MockSerial::new()), 10);
+    for _ in 0..64 {
+        rc.send_reliable(b"x".to_vec()).unwrap();
+    }
+    let err = rc.send_reliable(b"x".to_vec()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Busy);
+}
+
+#[test]
+fn test_reliable_retransmit() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.pause_recv();
+    let mut rc = ReliableConnection::new(conn, 3);
+    rc.send_reliable(b"x".to_vec()).unwrap();
+    for _ in 0..4 {
+        assert!(rc.poll().unwrap().is_none());
+    }
+    assert_eq!(rc.retransmits(), 1);
+    assert_eq!(rc.get_ref().get_ref().in_flight, vec![0xFF, 3, 0, 0, 0, b'x', 0xFF, 3, 0, 0, 0, b'x']);
+
+    // the duplicate is acknowledged but delivered only once
+    rc.get_mut().resume_recv();
+    let mut delivered = Vec::new();
+    for _ in 0..10 {
+        if let Some(frame) = rc.poll().unwrap() {
+            delivered.push(frame);
+        }
+    }
+    assert_eq!(delivered, vec![b"x".to_vec()]);
+    assert_eq!(rc.unacked_count(), 0);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_reliable_retransmit_timeout() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.pause_recv();
+    // the tick count no longer applies
+    let mut rc = ReliableConnection::new(conn, 1);
+    rc.set_retransmit_timeout(Some(std::time::Duration::from_secs(3600)));
+    let seq = rc.send_reliable(b"x".to_vec()).unwrap();
+    for _ in 0..4 {
+        assert!(rc.poll().unwrap().is_none());
+    }
+    assert_eq!(rc.retransmits(), 0);
+    assert!(rc.elapsed_since_send(seq).unwrap() < std::time::Duration::from_secs(3600));
+    assert!(rc.elapsed_since_send(seq.wrapping_add(1)).is_none());
+
+    rc.set_retransmit_timeout(Some(std::time::Duration::from_secs(0)));
+    rc.poll().unwrap();
+    assert_eq!(rc.retransmits(), 1);
+}
+
+#[test]
+fn test_encode_frame() {
+    assert_eq!(encode_frame(b"ab").unwrap(), vec![0xFF, 2, 0, b'a', b'b']);
+    assert_eq!(encode_frame(b"").unwrap(), vec![0xFF, 0, 0]);
+    assert!(encode_frame(&vec![0; 70_000]).is_err());
+
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.pause_recv();
+    conn.schedule_send(b"xyz".to_vec()).unwrap();
+    conn.block_until_send_done().unwrap();
+    assert_eq!(conn.get_ref().in_flight, encode_frame(b"xyz").unwrap());
+}
+
+/// A connection with every framing option away from its default.
+fn configured_connection() -> FramedConnection<MockSerial> {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_length_width(LengthWidth::U8);
+    conn.set_endianness(Endianness::Big);
+    conn.set_checksum(true);
+    conn.set_escaping(true).unwrap();
+    conn.set_tagging(true);
+    conn.set_magic(&[0xA5]).unwrap();
+    conn.set_auto_chunk(Some(3));
+    conn
+}
+
+#[test]
+fn test_encode_frame_with_config() {
+    assert_eq!(encode_frame_with(&FrameConfig::new(), b"ab").unwrap(), encode_frame(b"ab").unwrap());
+
+    let mut conn = configured_connection();
+    conn.pause_recv();
+    let frame = vec![1, 0xFF, 2, ESC, 3];
+    conn.schedule_send(frame.clone()).unwrap();
+    conn.block_until_send_done().unwrap();
+    let wire = encode_frame_with(conn.frame_config(), &frame).unwrap();
+    assert_eq!(conn.get_ref().in_flight, wire);
+    // two chunks, each with a sentinel, and the data bytes escaped
+    assert_eq!(wire.iter().filter(|&&b| b == 0xFF).count(), 2);
+    assert_ne!(wire, encode_frame(&frame).unwrap());
+
+    let mut config = FrameConfig::new();
+    config.set_length_width(LengthWidth::U8);
+    assert!(encode_frame_with(&config, &[0; 256]).is_err());
+    config.set_framing_mode(FramingMode::FixedSize(2));
+    assert_eq!(encode_frame_with(&config, b"ab").unwrap(), vec![0xFF, b'a', b'b']);
+    assert_eq!(encode_frame_with(&config, b"a").unwrap_err().kind(), ErrorKind::WrongFrameSize);
+}
+
+#[test]
+fn test_frame_decoder_with_config() {
+    let mut conn = configured_connection();
+    conn.pause_recv();
+    let frame = vec![1, 0xFF, 2, ESC, 3];
+    conn.schedule_send(frame.clone()).unwrap();
+    conn.block_until_send_done().unwrap();
+
+    let mut decoder = FrameDecoder::with_config(conn.frame_config().clone());
+    assert_eq!(decoder.config(), conn.frame_config());
+    let mut frames = Vec::new();
+    for &byte in conn.get_ref().in_flight.iter() {
+        if let Some(frame) = decoder.push(byte).unwrap() {
+            frames.push(frame);
+        }
+    }
+    assert_eq!(frames, vec![frame.clone()]);
+
+    // a default decoder cannot parse these frames
+    let mut decoder = FrameDecoder::new();
+    let decoded: Vec<_> = conn.get_ref().in_flight.iter().filter_map(|&b| decoder.push(b).ok()).collect();
+    assert!(!decoded.contains(&Some(frame)));
+
+    // a corrupt frame is reported once, then decoding continues
+    let mut config = FrameConfig::new();
+    config.set_checksum(true);
+    let mut wire = encode_frame_with(&config, b"ok").unwrap();
+    let last = wire.len() - 1;
+    wire[last] ^= 1;
+    wire.extend(encode_frame_with(&config, b"ok").unwrap());
+    let mut decoder = FrameDecoder::with_config(config);
+    let mut errors = 0;
+    let mut frames = Vec::new();
+    for &byte in wire.iter() {
+        match decoder.push(byte) {
+            Ok(Some(frame)) => frames.push(frame),
+            Ok(None) => {},
+            Err(e) => {
+                assert_eq!(e.kind(), ErrorKind::ChecksumMismatch);
+                errors += 1;
+            },
+        }
+    }
+    assert_eq!(errors, 1);
+    assert_eq!(frames, vec![b"ok".to_vec()]);
+}
+
+#[test]
+fn test_frame_decoder() {
+    let mut decoder = FrameDecoder::new();
+    let mut wire = vec![0x00];
+    wire.extend(encode_frame(b"ab").unwrap());
+    wire.extend(encode_frame(b"").unwrap());
+    let mut frames = Vec::new();
+    for &byte in wire.iter() {
+        if let Some(frame) = decoder.push(byte).unwrap() {
+            frames.push(frame);
+        }
+    }
+    assert_eq!(frames, vec![b"ab".to_vec(), b"".to_vec()]);
+    assert!(!decoder.is_receiving());
+    assert_eq!(decoder.push(0xFF).unwrap(), None);
+    assert!(decoder.is_receiving());
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_recv_buf_reserve() {
+    let mut conn = FramedConnection::new(BenchSerial::new(64, 64));
+    conn.get_mut().feed_rx(&[0xFF, 0xE8, 0x03, b'a']);
+    conn.tick().unwrap();
+    assert!(conn.heap_bytes() >= 1000);
+
+    // an unchecked huge length is not allocated up front
+    let mut conn = FramedConnection::new(BenchSerial::new(64, 64));
+    conn.get_mut().feed_rx(&[0xFF, 0xFF, 0xFF, b'a']);
+    conn.tick().unwrap();
+    assert!(conn.heap_bytes() < 0xFFFF);
+
+    let mut conn = FramedConnection::new_with_max_recv_len(BenchSerial::new(64, 64), 0xFFFF);
+    conn.get_mut().feed_rx(&[0xFF, 0xFF, 0xFF, b'a']);
+    conn.tick().unwrap();
+    assert!(conn.heap_bytes() >= 0xFFFF);
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_bulk_write() {
+    let original: Vec<u8> = (0..600).map(|i| i as u8).collect();
+    for round in 0..4 {
+        let mut conn = FramedConnection::new(BenchSerial::new(16, 16));
+        conn.set_bulk_write(true);
+        match round {
+            0 => {},
+            1 => {
+                conn.set_escaping(true).unwrap();
+                conn.set_checksum(true);
+            },
+            2 => {
+                conn.set_auto_chunk(Some(100));
+                conn.set_tx_chunk_yield(Some(7));
+            },
+            _ => conn.set_tx_rate_limit(10, 1),
+        }
+        conn.schedule_send(original.clone()).unwrap();
+        while !conn.tick().unwrap().recv_is_done {}
+        assert!(conn.get_frame().unwrap() == original);
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_loopback() {
+    let (mut a, mut b) = loopback();
+    a.schedule_send(b"ping".to_vec()).unwrap();
+    a.block_until_send_done().unwrap();
+    assert_eq!(b.get_ref().rx_pending(), encode_frame(b"ping").unwrap().len());
+    b.schedule_send(b"pong".to_vec()).unwrap();
+    b.block_until_send_done().unwrap();
+    while !a.tick().unwrap().recv_is_done {}
+    while !b.tick().unwrap().recv_is_done {}
+    assert_eq!(a.get_frame().unwrap(), b"pong");
+    assert_eq!(b.get_frame().unwrap(), b"ping");
+    assert_eq!(b.get_ref().rx_pending(), 0);
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_loopback_threads() {
+    let (mut a, mut b) = loopback();
+    let echo = std::thread::spawn(move || {
+        while !b.tick().unwrap().recv_is_done {
+            std::thread::yield_now();
+        }
+        let frame = b.get_frame().unwrap();
+        b.schedule_send(frame).unwrap();
+        b.block_until_send_done().unwrap();
+    });
+    a.schedule_send(b"ping".to_vec()).unwrap();
+    a.block_until_send_done().unwrap();
+    while !a.tick().unwrap().recv_is_done {
+        std::thread::yield_now();
+    }
+    assert_eq!(a.get_frame().unwrap(), b"ping");
+    echo.join().unwrap();
+}
+
+#[test]
+fn test_available_frames() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = [encode_frame(b"a").unwrap(), encode_frame(b"b").unwrap(), encode_frame(b"c").unwrap()].concat();
+    let mut conn = FramedConnection::new(ser);
+    assert_eq!(conn.available_frames(), 0);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert_eq!(conn.available_frames(), 3);
+    assert!(conn.get_frame().unwrap() == b"a");
+    assert_eq!(conn.available_frames(), 2);
+    assert!(conn.get_frame().unwrap() == b"b");
+    assert!(conn.get_frame().unwrap() == b"c");
+    assert!(!conn.frame_ready());
+
+    // a frame with a wrong checksum stops reading until it is reported
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 1, 0, b'a', 0, 0, 0xFF, 1, 0, b'b', 0, 0];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_checksum(true);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert_eq!(conn.available_frames(), 1);
+    assert_eq!(conn.get_frame().unwrap_err().kind(), ErrorKind::ChecksumMismatch);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert_eq!(conn.get_frame().unwrap_err().kind(), ErrorKind::ChecksumMismatch);
+}
+
+#[cfg(feature = "test-util")]
+#[cfg(feature = "std")]
+#[test]
+fn test_send_frame_recv_frame() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.send_frame(b"abc".to_vec()).unwrap();
+    assert!(!conn.is_sending());
+    assert!(conn.recv_frame().unwrap() == b"abc");
+    let (mut a, mut b) = loopback();
+    a.send_frame(b"ping".to_vec()).unwrap();
+    assert!(b.recv_frame().unwrap() == b"ping");
+}
+
+#[test]
+fn test_schedule_send_slice() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    let staging = [b'x'; 100];
+    conn.schedule_send_slice(&staging).unwrap();
+    conn.block_until_send_done().unwrap();
+    while !conn.tick().unwrap().recv_is_done {}
+    assert!(conn.get_frame().unwrap() == staging[..]);
+    let heap_bytes = conn.heap_bytes();
+    // the buffer of the sent frame is reused
+    conn.schedule_send_slice(&staging[..50]).unwrap();
+    assert_eq!(conn.heap_bytes(), heap_bytes);
+    conn.block_until_send_done().unwrap();
+    while !conn.tick().unwrap().recv_is_done {}
+    assert!(conn.get_frame().unwrap() == staging[..50]);
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_progress() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 3];
+    let mut conn = FramedConnection::new(ser);
+    assert_eq!(conn.recv_progress(), RecvProgress::Idle);
+    conn.tick().unwrap();
+    assert_eq!(conn.recv_progress(), RecvProgress::Header);
+    conn.get_mut().in_flight = vec![0, b'a'];
+    conn.tick().unwrap();
+    assert_eq!(conn.recv_progress(), RecvProgress::Data { received: 1, length: 3 });
+    assert_eq!(conn.recv_progress().to_string(), "received 1 of 3 bytes");
+
+    // the FIFO takes the sentinel, the header and one byte
+    let mut conn = FramedConnection::new(BenchSerial::new(4, 0));
+    assert_eq!(conn.send_progress(), None);
+    conn.schedule_send(vec![0; 10]).unwrap();
+    assert_eq!(conn.send_progress(), Some(SendProgress { sent: 0, total: 10 }));
+    conn.tick().unwrap();
+    assert_eq!(conn.send_progress(), Some(SendProgress { sent: 1, total: 10 }));
+    assert_eq!(conn.send_progress().unwrap().to_string(), "sent 1 of 10 bytes");
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_zero_length_frame() {
+    // complete as soon as the header arrives, with no byte after it
+    let mut ser = MockSerial::new();
+    ser.in_flight = encode_frame(b"").unwrap();
+    let mut conn = FramedConnection::new(ser);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap().is_empty());
+
+    let (mut a, mut b) = loopback();
+    a.set_checksum(true);
+    b.set_checksum(true);
+    a.send_frame(Vec::new()).unwrap();
+    assert!(b.tick().unwrap().recv_is_done);
+    assert!(b.get_frame().unwrap().is_empty());
+}
+
+#[test]
+fn test_builder() {
+    let mut conn = FramedConnectionBuilder::new().build(MockSerial::new()).unwrap();
+    conn.schedule_send(b"abc".to_vec()).unwrap();
+    conn.block_until_send_done().unwrap();
+    #[cfg(feature = "capture")]
+    assert_eq!(conn.last_tx_wire_bytes().unwrap(), &encode_frame(b"abc").unwrap()[..]);
+
+    let builder = FramedConnectionBuilder::new()
+        .sentinel(0x7E)
+        .length_width(LengthWidth::U32)
+        .endian(BigEndian)
+        .checksum(true)
+        .escaping(true)
+        .max_recv_len(8);
+    let mut conn = builder.build(MockSerial::new()).unwrap();
+    let mut expected = FramedConnection::new_with_max_recv_len(MockSerial::new(), 8);
+    expected.set_sentinel(0x7E).unwrap();
+    expected.set_length_width(LengthWidth::U32);
+    expected.set_endianness(Endianness::Big);
+    expected.set_checksum(true);
+    expected.set_escaping(true).unwrap();
+    assert_eq!(Endianness::from(LittleEndian), Endianness::Little);
+    let native = if cfg!(target_endian = "big") { Endianness::Big } else { Endianness::Little };
+    assert_eq!(Endianness::from(NativeEndian), native);
+    for c in [&mut conn, &mut expected].iter_mut() {
+        c.schedule_send(vec![0x7E, 1, 2]).unwrap();
+        c.block_until_send_done().unwrap();
+    }
+    #[cfg(feature = "capture")]
+    assert_eq!(conn.last_tx_wire_bytes(), expected.last_tx_wire_bytes());
+    while !conn.tick().unwrap().recv_is_done {}
+    assert!(conn.get_frame().unwrap() == [0x7E, 1, 2]);
+    conn.get_mut().in_flight = vec![0x7E, 0, 0, 0, 9];
+    conn.tick().unwrap();
+    assert_eq!(conn.resync_count(), 1);
+}
+
+#[test]
+fn test_rx_noise_limit() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![1, 2, 3, 0xFF, 1, 0, b'a', 4, 5, 6, 7, 0xFF, 1, 0, b'b'];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_rx_noise_limit(Some(3));
+    // three bytes are within the limit, four are not
+    assert_eq!(conn.tick().map(|_| ()).unwrap_err().kind(), ErrorKind::RxNoise);
+    assert!(conn.frame_ready());
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"a");
+    assert!(conn.get_frame().unwrap() == b"b");
+    assert_eq!(conn.discarded_bytes(), 7);
+}
+
+#[test]
+fn test_in_band_errors() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 1, 0, b'b', 0x2B, 0x87, 1, 2, 0xFF, 1, 0, b'a', 0x2B, 0x87];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_checksum(true);
+    conn.set_rx_noise_limit(Some(1));
+    conn.set_in_band_errors(true);
+    conn.tick().unwrap();
+    let outcomes = conn.drain_outcomes();
+    assert_eq!(outcomes.len(), 3);
+    match outcomes[0] {
+        FrameOutcome::Error(ErrorKind::ChecksumMismatch, ref frame) => assert_eq!(frame, b"b"),
+        ref other => panic!("unexpected {:?}", other),
+    }
+    match outcomes[1] {
+        FrameOutcome::Error(ErrorKind::RxNoise, ref frame) => assert!(frame.is_empty()),
+        ref other => panic!("unexpected {:?}", other),
+    }
+    match outcomes[2] {
+        FrameOutcome::Good(ref info) => assert_eq!(info.data, b"a"),
+        ref other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(conn.checksum_errors(), 1);
+    assert!(conn.drain_outcomes().is_empty());
+
+    // errors before a frame taken with `get_frame()` are dropped
+    conn.get_mut().in_flight = vec![0xFF, 1, 0, b'b', 0x2B, 0x87, 0xFF, 1, 0, b'a', 0x2B, 0x87,
+                                    0xFF, 1, 0, b'b', 0x2B, 0x87];
+    conn.tick().unwrap();
+    assert!(conn.get_frame().unwrap() == b"a");
+    match conn.drain_outcomes()[..] {
+        [FrameOutcome::Error(ErrorKind::ChecksumMismatch, _)] => {},
+        ref other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_magic() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_magic(&[0xAA, 0x55]).unwrap();
+    test_buffer_on(&mut conn, b"abc");
+    #[cfg(feature = "capture")]
+    assert_eq!(conn.last_tx_wire_bytes().unwrap(), &[0xFF, 0xAA, 0x55, 3, 0, b'a', b'b', b'c'][..]);
+    assert_eq!(conn.wire_len(3), 8);
+    assert_eq!(conn.set_magic(&[0; MAX_MAGIC_LEN + 1]).unwrap_err().kind(), ErrorKind::InvalidFrame);
+
+    // a sentinel in the data is not a frame start without the magic
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 0x12, 0xFF, 0xFF, 0xAA, 0x55, 1, 0, b'a'];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_magic(&[0xAA, 0x55]).unwrap();
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"a");
+    assert_eq!(conn.discarded_bytes(), 1);
+
+    for round in 0..4 {
+        let mut conn = FramedConnection::new(MockSerial::new());
+        conn.set_magic(&[0xFF, ESC, 0x55]).unwrap();
+        match round {
+            0 => conn.set_checksum(true),
+            1 => conn.set_escaping(true).unwrap(),
+            2 => conn.set_tagging(true),
+            _ => conn.set_auto_chunk(Some(2)),
+        }
+        test_buffer_on(&mut conn, b"magic");
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_fragmented_connection() {
+    let (a, b) = loopback();
+    let mut a = FragmentedConnection::new(a, 60_000);
+    let mut b = FragmentedConnection::new(b, 60_000);
+    let blob: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+    a.send_large(&blob).unwrap();
+    assert_eq!(a.get_ref().pending_send_count(), 4);
+    let mut received = None;
+    while received.is_none() {
+        a.poll_large().unwrap();
+        received = b.poll_large().unwrap();
+    }
+    assert!(received.unwrap() == blob);
+
+    a.send_large(b"").unwrap();
+    let mut received = None;
+    while received.is_none() {
+        a.poll_large().unwrap();
+        received = b.poll_large().unwrap();
+    }
+    assert!(received.unwrap().is_empty());
+}
+
+#[test]
+fn test_fragment_errors() {
+    let fragment = |index: u32, count: u32, data: &[u8]| {
+        let mut frame = index.to_le_bytes().to_vec();
+        frame.extend_from_slice(&count.to_le_bytes());
+        frame.extend_from_slice(data);
+        encode_frame(&frame).unwrap()
+    };
+    let mut ser = MockSerial::new();
+    // a missing fragment, then one out of order, then a whole payload
+    ser.in_flight = [fragment(0, 3, b"a"), fragment(2, 3, b"c"), fragment(0, 2, b"x"),
+                     fragment(1, 2, b"y")].concat();
+    let mut conn = FragmentedConnection::new(FramedConnection::new(ser), 1);
+    assert_eq!(conn.poll_large().unwrap_err().kind(), ErrorKind::InvalidFrame);
+    assert_eq!(conn.poll_large().unwrap(), Some(b"xy".to_vec()));
+
+    // a new payload before the last one was complete
+    let mut ser = MockSerial::new();
+    ser.in_flight = [fragment(0, 2, b"a"), fragment(0, 1, b"b")].concat();
+    let mut conn = FragmentedConnection::new(FramedConnection::new(ser), 1);
+    assert_eq!(conn.poll_large().unwrap_err().kind(), ErrorKind::InvalidFrame);
+    assert_eq!(conn.poll_large().unwrap(), Some(b"b".to_vec()));
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_tick_bytes_moved() {
+    let mut conn = FramedConnection::new(BenchSerial::new(4, 4));
+    let progress = conn.tick().unwrap();
+    assert_eq!((progress.bytes_sent, progress.bytes_received), (0, 0));
+    conn.schedule_send(vec![1; 10]).unwrap();
+    // the FIFO drains into the receive side as bytes are written and read
+    let progress = conn.tick().unwrap();
+    assert!(progress.bytes_sent > 0);
+    assert!(progress.bytes_received > 0);
+    let mut sent = progress.bytes_sent;
+    let mut received = progress.bytes_received;
+    while !conn.frame_ready() {
+        let progress = conn.tick().unwrap();
+        sent += progress.bytes_sent;
+        received += progress.bytes_received;
+    }
+    assert_eq!(sent, 13);
+    assert_eq!(received, 13);
+    assert_eq!(conn.tick().unwrap().bytes_sent, 0);
+}
+
+#[test]
+fn test_recv_capacity() {
+    let mut conn = FramedConnection::new_with_capacity(MockSerial::new(), 64);
+    assert!(conn.recv_capacity() >= 64);
+    // the frame takes the buffer with it
+    test_buffer_on(&mut conn, b"abc");
+    assert_eq!(conn.recv_capacity(), 0);
+
+    let mut conn = FramedConnection::new_with_capacity(MockSerial::new(), 64);
+    conn.set_retain_recv_buffer(true);
+    test_buffer_on(&mut conn, b"abc");
+    assert!(conn.recv_capacity() >= 64);
+    test_buffer_on(&mut conn, &[0; 1000]);
+    assert!(conn.recv_capacity() >= 1000);
+    conn.shrink_recv_buffer();
+    assert_eq!(conn.recv_capacity(), 0);
+}
+
+#[test]
+fn test_try_schedule_send() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.try_schedule_send(b"first".to_vec()).unwrap();
+    let err = conn.try_schedule_send(b"second".to_vec()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Busy);
+    assert_eq!(conn.pending_send_count(), 1);
+    while !conn.tick().unwrap().recv_is_done {}
+    assert!(conn.get_frame().unwrap() == b"first");
+    assert!(!conn.is_sending());
+    conn.try_schedule_send(b"second".to_vec()).unwrap();
+}
+
+#[test]
+fn test_slip() {
+    let mut conn = SlipConnection::new(MockSerial::new());
+    conn.schedule_send(&[1, 0xC0, 2, 0xDB, 3]);
+    conn.schedule_send(b"");
+    conn.schedule_send(b"x");
+    assert_eq!(conn.get_ref().in_flight, Vec::<u8>::new());
+    // the mock echoes every byte as soon as it is written
+    let progress = conn.tick().unwrap();
+    assert!(progress.send_is_done);
+    assert_eq!(progress.bytes_sent, 14);
+    assert!(conn.get_frame().unwrap() == [1, 0xC0, 2, 0xDB, 3]);
+    // the empty frame is dropped
+    assert!(conn.get_frame().unwrap() == b"x");
+    assert_eq!(conn.get_frame().unwrap_err().kind(), ErrorKind::FrameNotAvailable);
+
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xC0, 0xC0, b'a', 0xDB, 0xDC, 0xDB, 0xDD, 0xDB, b'b', 0xC0, b'c'];
+    let mut conn = SlipConnection::new(ser);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.try_get_frame().unwrap().unwrap() == [b'a', 0xC0, 0xDB, b'b']);
+    assert!(!FrameTransport::tick(&mut conn).unwrap().recv_is_done);
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_slip_schedule_while_sending() {
+    // the device takes fewer bytes per tick than are scheduled
+    let mut conn = SlipConnection::new(BenchSerial::new(4, 4));
+    let mut received = Vec::new();
+    for i in 0..50u8 {
+        conn.schedule_send(&[i, 0xC0, i]);
+        conn.tick().unwrap();
+        while let Some(frame) = conn.try_get_frame().unwrap() {
+            received.push(frame);
+        }
+    }
+    for _ in 0..100 {
+        conn.tick().unwrap();
+        while let Some(frame) = conn.try_get_frame().unwrap() {
+            received.push(frame);
+        }
+    }
+    let expected: Vec<Vec<u8>> = (0..50u8).map(|i| vec![i, 0xC0, i]).collect();
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn test_error_eq() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    let err = conn.schedule_send(vec![0; 70_000]).unwrap_err();
+    assert_eq!(err, framed_serial::Error::from_kind(ErrorKind::FrameTooLong));
+    assert_eq!(err.clone(), err);
+    assert!(err != framed_serial::Error::with_detail(ErrorKind::FrameTooLong, "detail".into()));
+}
+
+#[test]
+fn test_drain_frames() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 1, 0, b'a', 0xFF, 2, 0, b'b', b'c', 0xFF, 2, 0, b'd'];
+    let mut conn = FramedConnection::new(ser);
+    assert!(conn.drain_frames().is_empty());
+    conn.tick().unwrap();
+    assert_eq!(conn.drain_frames(), vec![b"a".to_vec(), b"bc".to_vec()]);
+    assert!(conn.drain_frames().is_empty());
+    assert!(conn.is_receiving());
+    conn.get_mut().in_flight = vec![b'e'];
+    conn.tick().unwrap();
+    assert_eq!(conn.drain_frames(), vec![b"de".to_vec()]);
+}
+
+#[test]
+fn test_drain_frames_in() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 1, 0, b'a', 0xFF, 2, 0, b'b', b'c', 0xFF, 0, 0];
+    let mut conn = FramedConnection::new(ser);
+    let mut arena = b"x".to_vec();
+    assert_eq!(conn.drain_frames_in(&mut arena).count(), 0);
+    conn.tick().unwrap();
+    let frames: Vec<&[u8]> = conn.drain_frames_in(&mut arena).collect();
+    assert_eq!(frames, vec![&b"a"[..], b"bc", b""]);
+    assert_eq!(arena, b"xabc");
+    assert_eq!(conn.drain_frames_in(&mut arena).count(), 0);
+}
+
+#[cfg(feature = "tokio")]
+extern crate tokio_util;
+
+#[cfg(feature = "tokio")]
+extern crate bytes;
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_tokio_codec() {
+    use tokio_util::codec::{Decoder, Encoder};
+    let mut codec = framed_serial::FramedCodec::new();
+    let mut buf = bytes::BytesMut::new();
+    codec.encode(b"hello".to_vec(), &mut buf).unwrap();
+    assert_eq!(&buf[..], &encode_frame(b"hello").unwrap()[..]);
+    codec.encode(b"world".to_vec(), &mut buf).unwrap();
+    let wire = buf.split().freeze();
+    let first_len = encode_frame(b"hello").unwrap().len();
+
+    // garbage, then the first frame cut short
+    let mut src = bytes::BytesMut::new();
+    src.extend_from_slice(b"xy");
+    src.extend_from_slice(&wire[..first_len - 1]);
+    assert_eq!(codec.decode(&mut src).unwrap(), None);
+
+    // the rest of the first frame and the second frame
+    src.extend_from_slice(&wire[first_len - 1..]);
+    assert_eq!(codec.decode(&mut src).unwrap(), Some(b"hello".to_vec()));
+    assert_eq!(&src[..], &wire[first_len..]);
+    assert_eq!(codec.decode(&mut src).unwrap(), Some(b"world".to_vec()));
+    assert!(src.is_empty());
+    assert_eq!(codec.decode(&mut src).unwrap(), None);
+
+    let too_long = vec![0; 0x10000];
+    let err = codec.encode(too_long, &mut buf).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_tokio_codec_checksum() {
+    use tokio_util::codec::{Decoder, Encoder};
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_checksum(true);
+    conn.set_length_width(LengthWidth::U8);
+    let mut codec = framed_serial::FramedCodec::with_config(conn.frame_config().clone());
+    assert_eq!(codec.config(), conn.frame_config());
+
+    // the connection receives what the codec sends
+    let mut buf = bytes::BytesMut::new();
+    codec.encode(b"crc".to_vec(), &mut buf).unwrap();
+    assert_eq!(buf.len(), 1 + 1 + 3 + 2);
+    conn.get_mut().in_flight = buf.to_vec();
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert_eq!(conn.get_frame().unwrap(), b"crc".to_vec());
+
+    // the codec receives what the connection sends
+    conn.pause_recv();
+    conn.schedule_send(b"back".to_vec()).unwrap();
+    conn.block_until_send_done().unwrap();
+    let wire = conn.get_ref().in_flight.clone();
+    let mut src = bytes::BytesMut::from(&wire[..]);
+    assert_eq!(codec.decode(&mut src).unwrap(), Some(b"back".to_vec()));
+
+    // a corrupt frame is an error, and the next frame decodes
+    let mut corrupt = wire.clone();
+    let last = corrupt.len() - 1;
+    corrupt[last] ^= 0x55;
+    let mut src = bytes::BytesMut::from(&corrupt[..]);
+    src.extend_from_slice(&wire);
+    let err = codec.decode(&mut src).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(codec.decode(&mut src).unwrap(), Some(b"back".to_vec()));
+    assert!(src.is_empty());
+}
+
+#[cfg(feature = "defmt")]
+extern crate defmt;
+
+/// Counts the bytes of encoded `defmt` frames.
+#[cfg(feature = "defmt")]
+static DEFMT_BYTES: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[cfg(feature = "defmt")]
+#[defmt::global_logger]
+struct CountingLogger;
+
+#[cfg(feature = "defmt")]
+unsafe impl defmt::Logger for CountingLogger {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(bytes: &[u8]) {
+        DEFMT_BYTES.fetch_add(bytes.len(), std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+// Normally provided by the `defmt.x` linker script of the target.
+#[cfg(feature = "defmt")]
+defmt::timestamp!("{=u32}", 0);
+
+#[cfg(feature = "defmt")]
+#[test]
+fn test_defmt_format() {
+    use std::sync::atomic::Ordering;
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_checksum(true);
+    conn.get_mut().in_flight = vec![0xFF, 1, 0, b'a', 0, 0];
+    conn.tick().unwrap();
+    let err = conn.get_frame().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ChecksumMismatch);
+
+    let start = DEFMT_BYTES.load(Ordering::SeqCst);
+    defmt::error!("{}", err.kind());
+    assert!(DEFMT_BYTES.load(Ordering::SeqCst) > start);
+
+    // the detail text is sent along with the kind
+    let err = framed_serial::Error::with_detail(ErrorKind::InvalidFrame, "x".repeat(50));
+    let start = DEFMT_BYTES.load(Ordering::SeqCst);
+    defmt::error!("{}", err);
+    assert!(DEFMT_BYTES.load(Ordering::SeqCst) - start >= 50);
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_flush_with_limit() {
+    // the FIFO never drains, as if the peer stopped reading
+    let mut conn = FramedConnection::new(BenchSerial::new(4, 0));
+    conn.schedule_send(b"abcdef".to_vec()).unwrap();
+    let err = conn.flush_with_limit(10).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    assert_eq!(conn.pending_send_count(), 1);
+    let io_err: std::io::Error = err.into();
+    assert_eq!(io_err.kind(), std::io::ErrorKind::WouldBlock);
+
+    let mut conn = FramedConnection::new(BenchSerial::new(4, 1));
+    conn.schedule_send(b"abcdef".to_vec()).unwrap();
+    conn.schedule_send(b"gh".to_vec()).unwrap();
+    conn.flush_with_limit(100).unwrap();
+    assert_eq!(conn.pending_send_count(), 0);
+    conn.flush_with_limit(0).unwrap();
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_trailing_bytes_after_frame() {
+    let first = encode_frame(b"first").unwrap();
+    let second = encode_frame(b"second").unwrap();
+    let half = second.len() / 2;
+    let mut serial = BenchSerial::new(64, 64);
+    serial.feed_rx(&first);
+    serial.feed_rx(&second[..half]);
+    let mut conn = FramedConnection::new(serial);
+
+    // the start of the second frame is read along with the first
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert_eq!(conn.get_frame().unwrap(), b"first");
+    assert!(conn.is_receiving());
+    assert_eq!(conn.recv_progress(), RecvProgress::Data { received: half - 3, length: 6 });
+
+    conn.get_mut().feed_rx(&second[half..]);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert_eq!(conn.get_frame().unwrap(), b"second");
+    assert!(!conn.is_receiving());
+}
+
+#[test]
+fn test_heap_bytes() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_retain_recv_buffer(true);
+    let empty = conn.heap_bytes();
+
+    // a scheduled frame is counted
+    conn.schedule_send(vec![b'x'; 1000]).unwrap();
+    assert!(conn.heap_bytes() >= empty + 1000);
+    conn.block_until_send_done().unwrap();
+    while !conn.tick().unwrap().recv_is_done {}
+    assert!(conn.get_frame().unwrap() == vec![b'x'; 1000]);
+
+    // the retained receive buffer is counted until it is shrunk
+    let inflated = conn.heap_bytes();
+    conn.shrink_recv_buffer();
+    assert!(inflated - conn.heap_bytes() >= 1000);
+}
+
+#[test]
+fn test_frame_ready_without_tick() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = encode_frame(b"ab").unwrap();
+    let mut conn = FramedConnection::new(ser);
+    // bytes waiting in the device are not a frame yet
+    assert!(!conn.frame_ready());
+    assert_eq!(conn.get_ref().in_flight.len(), 5);
+
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.frame_ready());
+    assert!(conn.frame_ready());
+    assert!(conn.get_frame().unwrap() == b"ab");
+    assert!(!conn.frame_ready());
+
+    // a complete frame with a wrong checksum is ready, and reported by get_frame()
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 1, 0, b'a', 0, 0];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_checksum(true);
+    assert!(!conn.frame_ready());
+    conn.tick().unwrap();
+    assert!(conn.frame_ready());
+    assert_eq!(conn.get_frame().unwrap_err().kind(), ErrorKind::ChecksumMismatch);
+    assert!(!conn.frame_ready());
+}
+
+#[test]
+fn test_adversarial_lengths() {
+    // A header declaring the largest frame, which never arrives.
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 0xFF, 0xFF, 1, 2, 3];
+    let mut conn = FramedConnection::new(ser);
+    assert!(!conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().is_err());
+
+    let mut conn = FramedConnection::new(MockSerial::new());
+    assert_eq!(conn.schedule_send(vec![0; u16::MAX as usize + 1]).unwrap_err().kind(), ErrorKind::FrameTooLong);
+    test_buffer_on(&mut conn, &vec![0xFF; u16::MAX as usize]);
+    test_buffer_on(&mut conn, b"");
+    conn.set_length_width(LengthWidth::U8);
+    assert_eq!(conn.schedule_send(vec![0; 256]).unwrap_err().kind(), ErrorKind::FrameTooLong);
+
+    // The largest u32 length does not allocate up front.
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 1, 2, 3];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_length_width(LengthWidth::U32);
+    assert!(!conn.tick().unwrap().recv_is_done);
+    assert!(conn.recv_capacity() <= 4096);
+    assert_eq!(conn.get_frame().unwrap_err().kind(), ErrorKind::FrameNotAvailable);
+
+    // Lengths over the limit are dropped once the header is complete.
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0xFF, 1, 0, b'a'];
+    let mut conn = FramedConnection::new_with_max_recv_len(ser, 8);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert_eq!(conn.resync_count(), 1);
+    assert!(conn.get_frame().unwrap() == b"a");
+
+    // Varints longer than five bytes, or over a u32, are dropped.
+    for wire in &[vec![0xFF, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01, 0xFF, 1, b'a'],
+                  vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x10, 0xFF, 1, b'a']] {
+        let mut ser = MockSerial::new();
+        ser.in_flight = wire.clone();
+        let mut conn = FramedConnection::new(ser);
+        conn.set_length_width(LengthWidth::Varint);
+        assert!(conn.tick().unwrap().recv_is_done);
+        assert_eq!(conn.resync_count(), 1);
+        assert!(conn.get_frame().unwrap() == b"a");
+    }
+
+    // A truncated varint is not a frame.
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 0x80, 0x80];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_length_width(LengthWidth::Varint);
+    assert!(!conn.tick().unwrap().recv_is_done);
+    assert!(conn.is_receiving());
+    assert_eq!(conn.get_frame().unwrap_err().kind(), ErrorKind::FrameNotAvailable);
+}
+
+#[test]
+fn test_frame_tee() {
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let seen2 = seen.clone();
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_frame_tee(Some(Box::new(move |dir, frame: &[u8]| {
+        seen2.lock().unwrap().push((dir, frame.to_vec()));
+    })));
+    test_buffer_on(&mut conn, b"abc");
+    assert_eq!(*seen.lock().unwrap(), vec![(Direction::Tx, b"abc".to_vec()),
+                                           (Direction::Rx, b"abc".to_vec())]);
+}
+
+#[test]
+fn test_fixed_size_framing() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0x00, 0xFF, b'a', b'b', b'c', b'd'];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_framing_mode(FramingMode::FixedSize(4));
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"abcd");
+    assert!(conn.schedule_send(b"abc".to_vec()).is_err());
+    test_buffer_on(&mut conn, b"wxyz");
+
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_framing_mode(FramingMode::FixedSize(0));
+    test_buffer_on(&mut conn, b"");
+}
+
+#[test]
+fn test_auto_chunk() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_auto_chunk(Some(1000));
+    let original: Vec<u8> = (0..70_000).map(|i| i as u8).collect();
+    test_buffer_on(&mut conn, &original);
+    test_buffer_on(&mut conn, b"");
+    conn.set_auto_chunk(Some(3));
+    test_buffer_on(&mut conn, b"abcdef");
+    assert_eq!(conn.last_tx_index(), Some(2));
+}
+
+#[test]
+fn test_tx_rate_limit() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_tx_rate_limit(4, 2);
+    // 12 bytes on the wire: 4 in the first window, 4 in the second of which
+    // the second tick sends nothing, and 4 in the third
+    conn.schedule_send(b"abcdefghi".to_vec()).unwrap();
+    assert!(!conn.tick().unwrap().send_is_done);
+    assert!(!conn.tick().unwrap().send_is_done);
+    assert!(!conn.tick().unwrap().send_is_done);
+    assert!(conn.tick().unwrap().send_is_done);
+    assert!(conn.get_frame().unwrap() == b"abcdefghi");
+}
+
+#[test]
+fn test_sentinel_is_idle() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 0xFF, 0xFF, 2, 0, b'a', 0xFF, 0xFF, 0xFF, 1, 0, 0xFF];
+    let mut conn = FramedConnection::new(ser);
+    conn.set_sentinel_is_idle(true);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"a\xFF");
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"\xFF");
+    assert!(conn.schedule_send(vec![0; 255]).is_err());
+    test_buffer_on(&mut conn, b"abc");
+}
+
+#[test]
+fn test_ticks_to_send() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    assert_eq!(conn.wire_len(5), 8);
+    assert_eq!(conn.ticks_to_send(5, 3), 3);
+    assert_eq!(conn.ticks_to_send(5, 8), 1);
+    assert_eq!(conn.ticks_to_send(5, 0), usize::MAX);
+    conn.set_tx_chunk_yield(Some(2));
+    assert_eq!(conn.ticks_to_send(5, 8), 4);
+    conn.set_auto_chunk(Some(2));
+    assert_eq!(conn.wire_len(5), 17);
+    assert_eq!(conn.wire_len(0), 4);
+}
+
+#[test]
+fn test_for_each_frame() {
+    let mut ser = MockSerial::new();
+    ser.in_flight = vec![0xFF, 1, 0, b'a', 0xFF, 2, 0, b'b', b'c'];
+    let mut conn = FramedConnection::new(ser);
+    let mut seen = Vec::new();
+    assert_eq!(conn.for_each_frame(|f| seen.push(f.to_vec())), 0);
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert_eq!(conn.for_each_frame(|f| seen.push(f.to_vec())), 2);
+    assert_eq!(seen, vec![b"a".to_vec(), b"bc".to_vec()]);
+    assert!(!conn.frame_ready());
+}
+
+#[test]
+fn test_on_send_idle() {
+    let n_idle = std::sync::Arc::new(std::sync::Mutex::new(0));
+    let n_idle2 = n_idle.clone();
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_on_send_idle(Some(Box::new(move || *n_idle2.lock().unwrap() += 1)));
+    conn.tick().unwrap();
+    assert_eq!(*n_idle.lock().unwrap(), 0);
+    test_buffer_on(&mut conn, b"abc");
+    conn.tick().unwrap();
+    assert_eq!(*n_idle.lock().unwrap(), 1);
+}
+
+fn assert_send<T: Send>() {}
+
+#[test]
+fn test_connection_is_send() {
+    assert_send::<FramedConnection<MockSerial>>();
+    #[cfg(feature = "test-util")]
+    assert_send::<FramedConnection<framed_serial::LoopbackSerial>>();
+}
+
+#[test]
+fn test_health() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_health_thresholds(HealthThresholds {
+        degraded_resync_rate: 0.5,
+        degraded_checksum_error_rate: 0.5,
+        stale_ticks: 2,
+        dead_ticks: 4,
+    });
+    conn.schedule_send(b"abc".to_vec()).unwrap();
+    assert!(conn.tick().unwrap().recv_is_done);
+    let health = conn.health();
+    assert_eq!(health.status, LinkStatus::Healthy);
+    assert_eq!(health.rx_queue_depth, 1);
+    assert_eq!(health.tx_queue_depth, 0);
+    conn.get_frame().unwrap();
+    let mut statuses = Vec::new();
+    for _ in 0..5 {
+        conn.tick().unwrap();
+        statuses.push(conn.health().status);
+    }
+    assert_eq!(statuses, vec![LinkStatus::Healthy, LinkStatus::Healthy, LinkStatus::Stale,
+                              LinkStatus::Stale, LinkStatus::Dead]);
+}
+
+#[test]
+fn test_frame_index() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    assert_eq!(conn.last_tx_index(), None);
+    for i in 0..3 {
+        conn.schedule_send(b"abc".to_vec()).unwrap();
+        assert!(conn.tick().unwrap().recv_is_done);
+        assert_eq!(conn.last_tx_index(), Some(i));
+        assert_eq!(conn.get_frame_info().unwrap().index, i);
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_pause_recv() {
+    let mut ser = BenchSerial::new(4, 4);
+    ser.feed_rx(&[0xFF, 5, 0, b'a', b'b', b'c', b'd', b'e']);
+    ser.set_rx_feed_rate(Some(4));
+    let mut conn = FramedConnection::new(ser);
+    assert!(!conn.tick().unwrap().recv_is_done);
+    conn.pause_recv();
+    for _ in 0..3 {
+        assert!(!conn.tick().unwrap().recv_is_done);
+    }
+    conn.resume_recv();
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"abcde");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_throughput() {
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.set_throughput_window(std::time::Duration::from_secs(10));
+    assert_eq!(conn.throughput().frames_per_sec, 0.0);
+    test_buffer_on(&mut conn, b"12345");
+    let throughput = conn.throughput();
+    // one frame sent and one frame received
+    assert_eq!(throughput.frames_per_sec, 0.2);
+    assert_eq!(throughput.bytes_per_sec, 1.0);
+}
+
+#[cfg(feature = "test-util")]
+#[cfg(feature = "std")]
+#[test]
+fn test_recv_frame_matching() {
+    let timeout = std::time::Duration::from_millis(10);
+    let mut ser = BenchSerial::new(64, 64);
+    ser.feed_rx(&[0xFF, 1, 0, b'a', 0xFF, 1, 0, b'b', 0xFF, 1, 0, b'c']);
+    let mut conn = FramedConnection::new(ser);
+    let found = conn.recv_frame_matching(|f| f == b"b", timeout).unwrap();
+    assert!(found.unwrap() == b"b");
+    let found = conn.recv_frame_matching(|f| f == b"x", timeout).unwrap();
+    assert!(found.is_none());
+    assert!(conn.get_frame().unwrap() == b"a");
+    assert!(conn.get_frame().unwrap() == b"c");
+    assert!(conn.get_frame().is_err());
+
+    // a corrupt frame is dropped while waiting
+    let mut ser = BenchSerial::new(64, 64);
+    ser.feed_rx(&[0xFF, 3, 0, b'b', b'a', b'd', 0, 0]);
+    ser.feed_rx(&[0xFF, 4, 0, b'g', b'o', b'o', b'd', 34, 128]);
+    let mut conn = FramedConnection::new(ser);
+    conn.set_checksum(true);
+    let found = conn.recv_frame_matching(|f| f == b"good", timeout).unwrap();
+    assert!(found.unwrap() == b"good");
+    assert_eq!(conn.checksum_errors(), 1);
+    assert!(!conn.frame_ready());
+}
+
+#[cfg(feature = "test-util")]
+#[cfg(feature = "std")]
+#[test]
+fn test_queue_order() {
+    let timeout = std::time::Duration::from_millis(10);
+    let wire = [0xFF, 1, 0, b'a', 0xFF, 1, 0, b'b', 0xFF, 1, 0, b'c'];
+    let mut ser = BenchSerial::new(64, 64);
+    ser.feed_rx(&wire);
+    let mut conn = FramedConnection::new(ser);
+    conn.set_queue_order(QueueOrder::Lifo);
+    assert!(conn.recv_frame_matching(|_| false, timeout).unwrap().is_none());
+    assert!(conn.get_frame().unwrap() == b"c");
+    assert!(conn.get_frame().unwrap() == b"b");
+    assert!(conn.get_frame().unwrap() == b"a");
+
+    let mut ser = BenchSerial::new(64, 64);
+    ser.feed_rx(&wire);
+    let mut conn = FramedConnection::new(ser);
+    conn.set_queue_order(QueueOrder::CoalesceLatest);
+    assert!(conn.recv_frame_matching(|_| false, timeout).unwrap().is_none());
+    let mut frames = Vec::new();
+    assert_eq!(conn.for_each_frame(|f| frames.push(f.to_vec())), 1);
+    assert_eq!(frames, vec![b"c".to_vec()]);
+    assert!(conn.get_frame().is_err());
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_recv_into_queue_order() {
+    let good = [0xFF, 4, 0, b'g', b'o', b'o', b'd', 34, 128];
+    let bad = [0xFF, 3, 0, b'b', b'a', b'd', 0, 0];
+    for &order in &[QueueOrder::Fifo, QueueOrder::Lifo, QueueOrder::CoalesceLatest] {
+        let mut ser = BenchSerial::new(64, 64);
+        ser.feed_rx(&good);
+        ser.feed_rx(&bad);
+        let mut conn = FramedConnection::new(ser);
+        conn.set_checksum(true);
+        conn.set_queue_order(order);
+        conn.tick().unwrap();
+        let mut results = Vec::new();
+        let mut buf = [0; 8];
+        for _ in 0..3 {
+            results.push(conn.recv_into(&mut buf).map(|n| buf[..n].to_vec()).map_err(|e| e.kind()));
+        }
+        let expected = match order {
+            QueueOrder::Fifo => vec![Ok(b"good".to_vec()),
+                                     Err(ErrorKind::ChecksumMismatch),
+                                     Err(ErrorKind::FrameNotAvailable)],
+            QueueOrder::Lifo => vec![Err(ErrorKind::ChecksumMismatch),
+                                     Ok(b"good".to_vec()),
+                                     Err(ErrorKind::FrameNotAvailable)],
+            QueueOrder::CoalesceLatest => vec![Err(ErrorKind::ChecksumMismatch),
+                                               Err(ErrorKind::FrameNotAvailable),
+                                               Err(ErrorKind::FrameNotAvailable)],
+        };
+        assert_eq!(results, expected, "{:?}", order);
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[cfg(feature = "std")]
+#[test]
+fn test_request() {
+    let timeout = std::time::Duration::from_millis(10);
+    let mut ser = BenchSerial::new(64, 64);
+    // telemetry arriving before our own request loops back
+    ser.feed_rx(&[0xFF, 1, 0, b't']);
+    let mut conn = FramedConnection::new(ser);
+    let response = conn.request(b"?ping".to_vec(), |f| f.starts_with(b"?"), timeout).unwrap();
+    assert!(response == b"?ping");
+    assert!(conn.get_frame().unwrap() == b"t");
+    assert!(conn.request(b"!".to_vec(), |f| f.starts_with(b"?"), timeout).is_err());
+
+    // a stale reply received earlier is not the response
+    let mut ser = BenchSerial::new(64, 64);
+    ser.feed_rx(&[0xFF, 2, 0, b'?', b'1']);
+    let mut conn = FramedConnection::new(ser);
+    conn.tick().unwrap();
+    assert_eq!(conn.available_frames(), 1);
+    let response = conn.request(b"?2".to_vec(), |f| f.starts_with(b"?"), timeout).unwrap();
+    assert!(response == b"?2");
+    assert!(conn.get_frame().unwrap() == b"?1");
+
+    // an unrelated corrupt frame does not fail the request
+    let mut ser = BenchSerial::new(64, 64);
+    ser.feed_rx(&[0xFF, 3, 0, b'b', b'a', b'd', 0, 0]);
+    let mut conn = FramedConnection::new(ser);
+    conn.set_checksum(true);
+    let response = conn.request(b"?3".to_vec(), |f| f.starts_with(b"?"), timeout).unwrap();
+    assert!(response == b"?3");
+    assert_eq!(conn.checksum_errors(), 1);
+}
+
+#[cfg(feature = "test-util")]
+#[cfg(feature = "std")]
+#[test]
+fn test_io_write() {
+    use std::io::Write;
+    let mut conn = FramedConnection::new(MockSerial::new());
+    conn.pause_recv();
+    conn.write_all(b"abc").unwrap();
+    conn.write_all(b"de").unwrap();
+    conn.flush().unwrap();
+    assert_eq!(conn.pending_send_count(), 0);
+    conn.resume_recv();
+    conn.tick().unwrap();
+    assert!(conn.get_frame().unwrap() == b"abc");
+    conn.tick().unwrap();
+    assert!(conn.get_frame().unwrap() == b"de");
+
+    let mut conn = FramedConnection::new(BenchSerial::new(4, 0));
+    conn.write_all(b"abcdef").unwrap();
+    assert_eq!(conn.flush().unwrap_err().kind(), std::io::ErrorKind::WouldBlock);
+
+    let mut conn = FramedConnection::new(MockSerial::new());
+    let err = conn.write(&vec![0; 70_000]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[cfg(feature = "std")]
+extern crate serial;
+
+/// A `serial::SerialPort` in memory, counting the calls made to it.
+#[cfg(feature = "std")]
+struct FakePort {
+    rx: std::collections::VecDeque<u8>,
+    tx: Vec<u8>,
+    reads: usize,
+    writes: usize,
+    /// Added to the byte count returned by `write()`.
+    write_extra: usize,
+}
+
+#[cfg(feature = "std")]
+impl FakePort {
+    fn new(rx: &[u8]) -> FakePort {
+        FakePort { rx: rx.iter().cloned().collect(), tx: Vec::new(), reads: 0, writes: 0, write_extra: 0 }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Read for FakePort {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reads += 1;
+        if self.rx.is_empty() {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        let n = std::cmp::min(buf.len(), self.rx.len());
+        for (dst, src) in buf.iter_mut().zip(self.rx.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Write for FakePort {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writes += 1;
+        self.tx.extend_from_slice(buf);
+        Ok(buf.len() + self.write_extra)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl serial::SerialPort for FakePort {
+    fn timeout(&self) -> std::time::Duration { std::time::Duration::from_millis(0) }
+    fn set_timeout(&mut self, _: std::time::Duration) -> serial::Result<()> { Ok(()) }
+    fn configure(&mut self, _: &serial::PortSettings) -> serial::Result<()> { Ok(()) }
+    fn reconfigure(&mut self, _: &dyn Fn(&mut dyn serial::SerialPortSettings) -> serial::Result<()>)
+        -> serial::Result<()> { Ok(()) }
+    fn set_rts(&mut self, _: bool) -> serial::Result<()> { Ok(()) }
+    fn set_dtr(&mut self, _: bool) -> serial::Result<()> { Ok(()) }
+    fn read_cts(&mut self) -> serial::Result<bool> { Ok(true) }
+    fn read_dsr(&mut self) -> serial::Result<bool> { Ok(true) }
+    fn read_ri(&mut self) -> serial::Result<bool> { Ok(false) }
+    fn read_cd(&mut self) -> serial::Result<bool> { Ok(true) }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_serialwrap_read_buffer() {
+    let mut wire = encode_frame(&[7; 100]).unwrap();
+    wire.extend(encode_frame(b"x").unwrap());
+    let port = framed_serial::SerialWrap::with_read_buffer(FakePort::new(&wire), 64);
+    let mut conn = FramedConnection::new(port);
+    while !conn.tick().unwrap().recv_is_done {}
+    assert!(conn.get_frame().unwrap() == vec![7; 100]);
+    assert!(conn.get_frame().unwrap() == b"x");
+    // two reads of data and one finding the port empty
+    assert_eq!(conn.get_ref().get_ref().reads, 3);
+
+    let port = framed_serial::SerialWrap::new(FakePort::new(&wire));
+    let mut conn = FramedConnection::new(port);
+    while !conn.tick().unwrap().recv_is_done {}
+    assert_eq!(conn.get_ref().get_ref().reads, wire.len() + 1);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_serialwrap_bulk_write() {
+    let mut conn = FramedConnection::new(framed_serial::SerialWrap::new(FakePort::new(&[])));
+    conn.set_bulk_write(true);
+    conn.schedule_send(vec![7; 100]).unwrap();
+    conn.block_until_send_done().unwrap();
+    let port = conn.get_ref().get_ref();
+    assert_eq!(port.tx, encode_frame(&[7; 100]).unwrap());
+    // the sentinel, two header bytes and the data
+    assert_eq!(port.writes, 4);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_serialwrap_bad_write_count() {
+    use embedded_serial::NonBlockingTx;
+    let mut port = FakePort::new(&[]);
+    port.write_extra = 1;
+    let mut wrap = framed_serial::SerialWrap::new(port);
+    assert_eq!(wrap.putc_try(b'a').unwrap_err().kind(), ErrorKind::TxError);
+    assert_eq!(wrap.puts_try(b"abc").unwrap_err().1.kind(), ErrorKind::TxError);
+}
+
+#[cfg(feature = "embedded-hal")]
+extern crate embedded_hal;
+
+#[cfg(feature = "embedded-hal")]
+extern crate nb;
+
+/// An `embedded_hal` serial driver whose every other write would block.
+#[cfg(feature = "embedded-hal")]
+struct HalPipe {
+    buf: std::collections::VecDeque<u8>,
+    block_write: bool,
+    fail: bool,
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::serial::Read<u8> for HalPipe {
+    type Error = ();
+    fn read(&mut self) -> nb::Result<u8, ()> {
+        if self.fail {
+            return Err(nb::Error::Other(()));
+        }
+        self.buf.pop_front().ok_or(nb::Error::WouldBlock)
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::serial::Write<u8> for HalPipe {
+    type Error = ();
+    fn write(&mut self, word: u8) -> nb::Result<(), ()> {
+        self.block_write = !self.block_write;
+        if self.block_write {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.buf.push_back(word);
+        Ok(())
+    }
+    fn flush(&mut self) -> nb::Result<(), ()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+#[test]
+fn test_hal_wrap() {
+    use embedded_serial::NonBlockingRx;
+    let rx = HalPipe { buf: encode_frame(b"hal").unwrap().into_iter().collect(), block_write: false, fail: false };
+    let tx = HalPipe { buf: Default::default(), block_write: false, fail: false };
+    let mut conn = FramedConnection::new(framed_serial::HalWrap::new(rx, tx));
+    conn.schedule_send(b"out".to_vec()).unwrap();
+    assert!(conn.tick().unwrap().recv_is_done);
+    assert!(conn.get_frame().unwrap() == b"hal");
+    conn.block_until_send_done().unwrap();
+    let (mut rx, tx) = conn.into_inner().into_inner();
+    assert_eq!(tx.buf.into_iter().collect::<Vec<u8>>(), encode_frame(b"out").unwrap());
+
+    rx.fail = true;
+    let mut wrap = framed_serial::HalWrap::new(rx, HalPipe { buf: Default::default(), block_write: false, fail: false });
+    assert_eq!(wrap.getc_try().unwrap_err().kind(), ErrorKind::RxError);
+}
 
 // Always compile this with std, but only run with device_connected.
 #[allow(dead_code)]